@@ -1,21 +1,91 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 #[cfg(feature = "tftp")]
 use std::net::IpAddr;
+#[cfg(feature = "tftp")]
+use uboot_tool::TftpOptions;
 
+use anyhow::Result;
 use structopt::StructOpt;
-use uboot_tool::{Result, UBootClient};
+use uboot_tool::{
+    AutobootCatch, AutobootStop, ClientOptions, DataBits, Endpoint, FlowControl, Parity, PortProbe,
+    ReconnectPolicy, Recorder, ReplayStream, SerialConfig, StopBits, TerminalKey, UBootClient,
+};
 
 #[derive(Debug, StructOpt, Clone, PartialEq)]
 #[structopt(about = "UBoot tool for IP Camera firmware management.")]
 pub struct Args {
-    /// Serial port
+    /// Serial port or console server URL (tcp://, telnet://, rfc2217://host:port)
     #[structopt(short, long, env = "SERIAL_PORT")]
-    pub port: Option<String>,
+    pub port: Option<Endpoint>,
+
+    /// Baud rate (or auto to detect it)
+    #[structopt(short, long, env = "SERIAL_BAUD", default_value = "115200", parse(try_from_str = parse_baud))]
+    pub baud: Baud,
+
+    /// Data bits (5, 6, 7, 8)
+    #[structopt(long, env = "SERIAL_DATA_BITS", default_value = "8", parse(try_from_str = parse_data_bits))]
+    pub data_bits: DataBits,
+
+    /// Parity (none, odd, even)
+    #[structopt(long, env = "SERIAL_PARITY", default_value = "none", parse(try_from_str = parse_parity))]
+    pub parity: Parity,
+
+    /// Stop bits (1, 2)
+    #[structopt(long, env = "SERIAL_STOP_BITS", default_value = "1", parse(try_from_str = parse_stop_bits))]
+    pub stop_bits: StopBits,
+
+    /// Flow control (none, software, hardware)
+    #[structopt(long, env = "SERIAL_FLOW_CONTROL", default_value = "none", parse(try_from_str = parse_flow_control))]
+    pub flow_control: FlowControl,
+
+    /// Do not lock serial port for exclusive access
+    #[structopt(long)]
+    pub shared: bool,
+
+    /// Initial DTR state (on, off)
+    #[structopt(long, parse(try_from_str = parse_switch))]
+    pub dtr: Option<bool>,
+
+    /// Initial RTS state (on, off)
+    #[structopt(long, parse(try_from_str = parse_switch))]
+    pub rts: Option<bool>,
+
+    /// Silence after which incomplete line is considered as partial (e.g. 50ms)
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub idle_timeout: Option<Duration>,
+
+    /// Time to wait shell prompt (e.g. 150ms)
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub prompt_timeout: Option<Duration>,
+
+    /// Maximum silence while command is running (e.g. 10s)
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub command_timeout: Option<Duration>,
+
+    /// Additional time per MiB for flash read and checksum (e.g. 2s)
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub timeout_per_mib: Option<Duration>,
+
+    /// Autoboot stop rule `pattern=keys` (e.g. `Autoboot in=<Esc><Esc>`, `Password:=secret<Enter>`)
+    #[structopt(long, number_of_values = 1)]
+    pub stop_autoboot: Vec<AutobootStop>,
+
+    /// Reopen port when it fails (e.g. USB serial adapter is unplugged)
+    #[structopt(long)]
+    pub reconnect: bool,
 
-    /// Baud rate
-    #[structopt(short, long, env = "SERIAL_BAUD", default_value = "115200")]
-    pub baud: u32,
+    /// Record console traffic to file
+    #[structopt(long, parse(from_os_str))]
+    pub record: Option<PathBuf>,
+
+    /// Replay recorded console traffic from file instead of using port
+    #[structopt(long, parse(from_os_str), conflicts_with = "port")]
+    pub replay: Option<PathBuf>,
+
+    /// Replay speed factor (e.g. 10 to play faster, `inf` to play without delays)
+    #[structopt(long, requires = "replay")]
+    pub replay_speed: Option<f64>,
 
     /// Path for backup and restore
     #[structopt(short = "f", long, env = "FILE_PATH", parse(from_os_str))]
@@ -26,25 +96,85 @@ pub struct Args {
     #[structopt(short, long, env = "IP_ADDRESS")]
     pub ip: Option<IpAddr>,
 
+    #[cfg(feature = "tftp")]
+    /// TFTP server port (0 to select free one, non-standard port does not require root)
+    #[structopt(long, env = "TFTP_PORT", default_value = "69")]
+    pub tftp_port: u16,
+
     /// Command
     #[structopt(subcommand)]
     pub command: Cmd,
 }
 
+/// Output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Yaml,
+    Toml,
+}
+
+/// Baud rate setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Baud {
+    Auto,
+    Rate(u32),
+}
+
 #[derive(Debug, StructOpt, Clone, PartialEq)]
 pub enum Cmd {
     /// Show available serial ports
-    Ports,
+    Ports {
+        /// Find ports which answer with U-Boot or Linux console
+        #[structopt(long)]
+        probe: bool,
+
+        /// Output format (text, json, yaml, toml)
+        #[structopt(long, default_value = "text", parse(try_from_str = parse_format))]
+        format: Format,
+    },
 
     #[cfg(feature = "tftp")]
     /// Show available networks
-    Networks,
+    Networks {
+        /// Output format (text, json, yaml, toml)
+        #[structopt(long, default_value = "text", parse(try_from_str = parse_format))]
+        format: Format,
+    },
 
     /// Stop autoboot when device connected
-    Login,
+    Login {
+        /// Repeat interrupt keys since power-on until prompt appears (for bootdelay=0)
+        #[structopt(long)]
+        catch: bool,
+
+        /// Interrupt keys to repeat (e.g. <Ctrl-C>, <Esc><Esc>)
+        #[structopt(long, default_value = "<Ctrl-C>")]
+        catch_keys: TerminalKey,
+
+        /// Interval between interrupt keys
+        #[structopt(long, default_value = "10ms", parse(try_from_str = parse_interval))]
+        catch_interval: Duration,
+
+        /// Time to wait prompt while catching
+        #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration))]
+        catch_deadline: Duration,
+    },
 
     /// Get system info
-    Info,
+    Info {
+        /// Output format (text, json, yaml, toml)
+        #[structopt(long, default_value = "text", parse(try_from_str = parse_format))]
+        format: Format,
+    },
+
+    /// Execute command and print its output
+    Exec {
+        /// Command line
+        #[structopt(required = true)]
+        command: Vec<String>,
+    },
 
     /// Backup environment variables to file
     DumpEnv,
@@ -54,17 +184,92 @@ pub enum Cmd {
         /// Parts to be dumped (all by default)
         #[structopt(short = "m", long)]
         part: Vec<String>,
+
+        /// Switch console to faster baud rate while dumping (e.g. 921600)
+        #[structopt(long)]
+        fast_baud: Option<u32>,
+
+        #[cfg(feature = "tftp")]
+        /// Transfer parts via TFTP (requires device IP)
+        #[structopt(long)]
+        tftp: bool,
     },
 }
 
 impl Args {
-    pub fn uboot_client(&self) -> Result<UBootClient> {
-        let port = self
-            .port
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("No port is set"))?;
-        let baud = self.baud;
-        UBootClient::new(port, baud)
+    pub async fn uboot_client(&self) -> Result<UBootClient> {
+        let client = if let Some(path) = &self.replay {
+            let replay = ReplayStream::open(path).await?;
+            UBootClient::from_transport(replay.speed(self.replay_speed.unwrap_or(1.0))?)
+        } else {
+            let port = self
+                .port
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("No port is set"))?;
+            let config = self.serial_config().await?;
+            if self.reconnect {
+                UBootClient::open_reconnecting(port, &config, ReconnectPolicy::default()).await?
+            } else {
+                UBootClient::open(port, &config).await?
+            }
+        };
+        client.set_options(self.client_options());
+        if let Some(path) = &self.record {
+            client.record(Recorder::create(path).await?).await?;
+        }
+        Ok(client)
+    }
+
+    pub fn client_options(&self) -> ClientOptions {
+        let mut options = ClientOptions::default();
+        if let Some(timeout) = self.idle_timeout {
+            options = options.idle_timeout(timeout);
+        }
+        if let Some(timeout) = self.prompt_timeout {
+            options = options.prompt_timeout(timeout);
+        }
+        if let Some(timeout) = self.command_timeout {
+            options = options.command_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout_per_mib {
+            options = options.timeout_per_mib(timeout);
+        }
+        options
+    }
+
+    /// Line settings with default baud rate when it should be detected
+    pub fn line_config(&self) -> SerialConfig {
+        let mut config = SerialConfig::default()
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .flow_control(self.flow_control)
+            .exclusive(!self.shared);
+        if let Some(dtr) = self.dtr {
+            config = config.dtr(dtr);
+        }
+        if let Some(rts) = self.rts {
+            config = config.rts(rts);
+        }
+        if let Baud::Rate(rate) = self.baud {
+            config = config.baud_rate(rate);
+        }
+        config
+    }
+
+    pub async fn serial_config(&self) -> Result<SerialConfig> {
+        let config = self.line_config();
+        Ok(match (self.baud, &self.port) {
+            (Baud::Rate(_), _) => config,
+            (Baud::Auto, Some(Endpoint::Serial(name))) => {
+                let rate = UBootClient::detect_baud(name, &config).await?;
+                eprintln!("Detected baud rate: {}", rate);
+                config.baud_rate(rate)
+            }
+            (Baud::Auto, _) => {
+                anyhow::bail!("Baud rate detection is supported for local serial ports only")
+            }
+        })
     }
 
     pub fn get_path(&self) -> Result<PathBuf> {
@@ -77,6 +282,11 @@ impl Args {
 
     //pub fn file_name(&self, name: AsRef<>)
 
+    #[cfg(feature = "tftp")]
+    pub fn tftp_options(&self) -> Result<TftpOptions> {
+        Ok(TftpOptions::new(self.get_ip()?).port(self.tftp_port))
+    }
+
     #[cfg(feature = "tftp")]
     pub fn get_ip(&self) -> Result<std::net::IpAddr> {
         let ip = self
@@ -107,6 +317,146 @@ impl Args {
     }
 }
 
+fn print_formatted(value: &impl serde::Serialize, format: Format) -> Result<()> {
+    let text = match format {
+        Format::Text => anyhow::bail!("Text output is not supported here"),
+        Format::Json => serde_json::to_string_pretty(value)? + "\n",
+        Format::Yaml => serde_yaml::to_string(value)?,
+        Format::Toml => toml::to_string(value)?,
+    };
+    print!("{}", text);
+    Ok(())
+}
+
+fn print_ports(probes: &[PortProbe], probe: bool) {
+    fn opt(value: &Option<String>) -> &str {
+        value.as_deref().unwrap_or("-")
+    }
+
+    print!(
+        "{:<16} {:<9} {:<9} {:<16} {:<16} {:<24}",
+        "PORT", "TYPE", "VID:PID", "SERIAL", "MANUFACTURER", "PRODUCT"
+    );
+    if probe {
+        print!(" CONSOLE");
+    }
+    println!();
+
+    for PortProbe {
+        port,
+        console,
+        error,
+    } in probes
+    {
+        let ids = match (port.vid, port.pid) {
+            (Some(vid), Some(pid)) => format!("{:04x}:{:04x}", vid, pid),
+            _ => "-".into(),
+        };
+        print!(
+            "{:<16} {:<9} {:<9} {:<16} {:<16} {:<24}",
+            port.name,
+            port.kind.as_str(),
+            ids,
+            opt(&port.serial_number),
+            opt(&port.manufacturer),
+            opt(&port.product)
+        );
+        if probe {
+            match (console, error) {
+                (_, Some(error)) => print!(" error: {}", error),
+                (Some(console), _) => print!(" {}", console.as_str()),
+                (None, None) => print!(" -"),
+            }
+        }
+        println!();
+    }
+}
+
+fn parse_format(src: &str) -> Result<Format> {
+    Ok(match src {
+        "text" => Format::Text,
+        "json" => Format::Json,
+        "yaml" => Format::Yaml,
+        "toml" => Format::Toml,
+        _ => anyhow::bail!("Invalid output format: {}", src),
+    })
+}
+
+fn parse_baud(src: &str) -> Result<Baud> {
+    Ok(match src {
+        "auto" => Baud::Auto,
+        _ => Baud::Rate(
+            src.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid baud rate: {}", src))?,
+        ),
+    })
+}
+
+fn parse_data_bits(src: &str) -> Result<DataBits> {
+    Ok(match src {
+        "5" => DataBits::Five,
+        "6" => DataBits::Six,
+        "7" => DataBits::Seven,
+        "8" => DataBits::Eight,
+        _ => anyhow::bail!("Invalid data bits: {}", src),
+    })
+}
+
+fn parse_parity(src: &str) -> Result<Parity> {
+    Ok(match src {
+        "none" | "n" => Parity::None,
+        "odd" | "o" => Parity::Odd,
+        "even" | "e" => Parity::Even,
+        _ => anyhow::bail!("Invalid parity: {}", src),
+    })
+}
+
+fn parse_stop_bits(src: &str) -> Result<StopBits> {
+    Ok(match src {
+        "1" => StopBits::One,
+        "2" => StopBits::Two,
+        _ => anyhow::bail!("Invalid stop bits: {}", src),
+    })
+}
+
+fn parse_flow_control(src: &str) -> Result<FlowControl> {
+    Ok(match src {
+        "none" => FlowControl::None,
+        "software" | "xonxoff" => FlowControl::Software,
+        "hardware" | "rtscts" => FlowControl::Hardware,
+        _ => anyhow::bail!("Invalid flow control: {}", src),
+    })
+}
+
+fn parse_switch(src: &str) -> Result<bool> {
+    Ok(match src {
+        "on" | "1" | "true" => true,
+        "off" | "0" | "false" => false,
+        _ => anyhow::bail!("Invalid switch state: {}", src),
+    })
+}
+
+fn parse_duration(src: &str) -> Result<Duration> {
+    let (value, unit) = src.split_at(src.find(|c: char| !c.is_ascii_digit()).unwrap_or(src.len()));
+    let value: u64 = value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid duration: {}", src))?;
+    Ok(match unit {
+        "" | "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        _ => anyhow::bail!("Invalid duration unit: {}", src),
+    })
+}
+
+fn parse_interval(src: &str) -> Result<Duration> {
+    let interval = parse_duration(src)?;
+    if interval.is_zero() {
+        anyhow::bail!("Interval must not be zero: {}", src);
+    }
+    Ok(interval)
+}
+
 pub struct ProgressBar {
     out: std::io::Stdout,
     msg: String,
@@ -124,7 +474,7 @@ impl ProgressBar {
             out: std::io::stdout(),
             msg,
             val: 100,
-            max: max,
+            max,
             bar_max,
             bar_val: 0,
         };
@@ -159,7 +509,7 @@ impl ProgressBar {
         use std::io::Write;
 
         self.set(self.max)?;
-        writeln!(&mut self.out, "")?;
+        writeln!(&mut self.out)?;
         Ok(())
     }
 }
@@ -172,15 +522,53 @@ impl Drop for ProgressBar {
 
 async fn run(args: Args) -> Result<()> {
     match &args.command {
-        Cmd::Ports => {
-            for port in UBootClient::ports()? {
-                println!("{}", port);
+        Cmd::Ports { probe, format } => {
+            let probes = if *probe {
+                UBootClient::probe_ports(&args.line_config()).await?
+            } else {
+                UBootClient::port_infos()?
+                    .into_iter()
+                    .map(|port| PortProbe {
+                        port,
+                        console: None,
+                        error: None,
+                    })
+                    .collect()
+            };
+            if *format == Format::Text {
+                print_ports(&probes, *probe);
+            } else {
+                #[derive(serde::Serialize)]
+                struct Ports<'a> {
+                    ports: &'a [PortProbe],
+                }
+                print_formatted(&Ports { ports: &probes }, *format)?;
             }
         }
 
         #[cfg(feature = "tftp")]
-        Cmd::Networks => {
-            for (name, networks) in UBootClient::networks()? {
+        Cmd::Networks { format } => {
+            let networks = UBootClient::networks()?;
+            if *format != Format::Text {
+                #[derive(serde::Serialize)]
+                struct Networks {
+                    networks: uboot_tool::Map<String, Vec<ipnetwork::IpNetwork>>,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    device_ip: Option<IpAddr>,
+                }
+                let device_ip = match args.ip {
+                    Some(_) => Some(args.get_ip()?),
+                    None => None,
+                };
+                return print_formatted(
+                    &Networks {
+                        networks,
+                        device_ip,
+                    },
+                    *format,
+                );
+            }
+            for (name, networks) in networks {
                 println!("{}:", name);
                 for network in networks {
                     println!("\t{}/{}", network.ip(), network.prefix());
@@ -192,22 +580,40 @@ async fn run(args: Args) -> Result<()> {
             }
         }
 
-        Cmd::Login => {
-            let mut client = args.uboot_client()?;
-            let prompt = client.shell_presence().await?;
+        Cmd::Login {
+            catch,
+            catch_keys,
+            catch_interval,
+            catch_deadline,
+        } => {
+            let mut client = args.uboot_client().await?;
+            let prompt = if *catch {
+                let catch = AutobootCatch::default()
+                    .keys(catch_keys.clone())
+                    .interval(*catch_interval)
+                    .deadline(*catch_deadline);
+                client.catch_autoboot(&catch).await?
+            } else {
+                client.shell_presence_with(&args.stop_autoboot).await?
+            };
             let prompt = core::str::from_utf8(&prompt)?;
             println!("prompt: {}", prompt);
         }
 
-        Cmd::Info => {
-            let mut client = args.uboot_client()?;
-            let _prompt = client.shell_presence().await?;
+        Cmd::Info { format } => {
+            let mut client = args.uboot_client().await?;
+            let _prompt = client.shell_presence_with(&args.stop_autoboot).await?;
 
-            let ver = client.get_version().await?;
+            let info = client.get_device_info().await?;
+            if *format != Format::Text {
+                return print_formatted(&info, *format);
+            }
+
+            let ver = &info.version;
             println!("U-Boot:\t{}.{}", ver.year, ver.month);
             println!("\trevision:\t{}-{}", ver.revision, ver.suffix);
 
-            let flash = client.get_flash_info().await?;
+            let flash = &info.flash;
             println!("Flash {}:", flash.kind.as_str());
             if flash.has_name() {
                 println!("\tname:\t{}", flash.name);
@@ -221,16 +627,16 @@ async fn run(args: Args) -> Result<()> {
             println!("\tsize:\t{:#08x}*{}", flash.size, flash.count);
             println!("\tblock:\t{:#08x}", flash.block);
 
-            let ram = client.get_ram_info().await?;
+            let ram = &info.ram;
             println!("RAM:");
             println!("\tbase:\t{:#08x}", ram.base);
             println!("\tsize:\t{:#08x}", ram.size);
 
-            let parts = client.get_mtd_parts().await?;
+            let parts = &info.mtd_parts;
             if !parts.is_empty() {
                 let mut total = 0;
                 println!("MTD Parts:");
-                for (name, region) in &parts {
+                for (name, region) in parts {
                     println!("\t{}:\t{:#08x} {:#08x}", name, region.base, region.size);
                     total += region.size;
                 }
@@ -238,12 +644,23 @@ async fn run(args: Args) -> Result<()> {
             }
         }
 
+        Cmd::Exec { command } => {
+            let mut client = args.uboot_client().await?;
+            let _prompt = client.shell_presence_with(&args.stop_autoboot).await?;
+
+            let output = client.run(command.join(" ")).await?;
+            for line in &output.lines {
+                println!("{}", line);
+            }
+            output.check()?;
+        }
+
         Cmd::DumpEnv => {
             use tokio::io::AsyncWriteExt;
 
             let path = args.get_path()?.join("env.txt");
-            let mut client = args.uboot_client()?;
-            let _prompt = client.shell_presence().await?;
+            let mut client = args.uboot_client().await?;
+            let _prompt = client.shell_presence_with(&args.stop_autoboot).await?;
 
             let environ = client.get_environ().await?;
             let mut file = tokio::fs::File::create(path).await?;
@@ -256,12 +673,24 @@ async fn run(args: Args) -> Result<()> {
             }
         }
 
-        Cmd::DumpMtd { part } => {
+        Cmd::DumpMtd {
+            part,
+            fast_baud,
+            #[cfg(feature = "tftp")]
+            tftp,
+        } => {
             use tokio::io::AsyncWriteExt;
 
+            #[cfg(feature = "tftp")]
+            let tftp = if *tftp {
+                Some(args.tftp_options()?)
+            } else {
+                None
+            };
+
             let dir = args.get_path()?;
-            let mut client = args.uboot_client()?;
-            let _prompt = client.shell_presence().await?;
+            let mut client = args.uboot_client().await?;
+            let _prompt = client.shell_presence_with(&args.stop_autoboot).await?;
 
             let ram = client.get_ram_info().await?;
             let address = ram.base + ram.size / 2;
@@ -269,7 +698,7 @@ async fn run(args: Args) -> Result<()> {
 
             // save parts info
             {
-                let path = dir.join(format!("mtd.txt"));
+                let path = dir.join("mtd.txt");
                 let mut file = tokio::fs::File::create(&path).await?;
                 file.write_all(b"# name size\n").await?;
                 for (name, region) in &parts {
@@ -284,23 +713,48 @@ async fn run(args: Args) -> Result<()> {
                 Box::new(part.iter()) as Box<dyn Iterator<Item = &String>>
             };
 
+            let old_baud = client.baud_rate();
+            if let Some(rate) = fast_baud {
+                match client.switch_baud(*rate).await {
+                    Ok(()) => println!("Baud rate is switched to {}", rate),
+                    Err(error) => eprintln!("Unable to switch baud rate: {}", error),
+                }
+            }
+
             println!("Dumping MTD parts...");
 
             // save parts contents
             for name in names {
                 if let Some(region) = parts.get(name) {
                     let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(10);
-                    let path = dir.join(format!("{}.bin", name));
+                    let file_name = format!("{}.bin", name);
+                    let path = dir.join(&file_name);
+
                     let file = tokio::fs::File::create(&path).await?;
 
                     tokio::task::spawn({
                         let mut client = client.clone();
                         let region = region.clone();
                         async move {
-                            if let Err(err) = client
-                                .dump_mtd_part(file, &region, address, progress_tx)
-                                .await
-                            {
+                            let result = async {
+                                #[cfg(feature = "tftp")]
+                                if let Some(tftp) = tftp {
+                                    return client
+                                        .dump_mtd_part_tftp(
+                                            &tftp,
+                                            file_name,
+                                            file,
+                                            &region,
+                                            address,
+                                            progress_tx,
+                                        )
+                                        .await;
+                                }
+                                client
+                                    .dump_mtd_part(file, &region, address, progress_tx)
+                                    .await
+                            };
+                            if let Err(err) = result.await {
                                 eprintln!("Error when dumping mtd part: {}", err);
                             }
                         }
@@ -315,6 +769,10 @@ async fn run(args: Args) -> Result<()> {
                     eprintln!("Unknown part: {}", name);
                 }
             }
+
+            if let Some(rate) = old_baud.filter(|rate| Some(*rate) != client.baud_rate()) {
+                client.switch_baud(rate).await?;
+            }
         }
     }
 
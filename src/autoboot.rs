@@ -0,0 +1,57 @@
+use tokio::time::Duration;
+
+use crate::terminal_key::TerminalKey;
+
+/// Minimum delay between interrupt keys
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Catching autoboot by repeating interrupt keys since power-on
+///
+/// Needed for boards with `bootdelay=0` which never print autoboot prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutobootCatch {
+    pub(crate) keys: TerminalKey,
+    pub(crate) interval: Duration,
+    pub(crate) deadline: Duration,
+}
+
+impl Default for AutobootCatch {
+    fn default() -> Self {
+        Self {
+            keys: TerminalKey::Ctrl(b'C'),
+            interval: Duration::from_millis(10),
+            deadline: Duration::from_secs(30),
+        }
+    }
+}
+
+impl AutobootCatch {
+    /// Interrupt keys (Ctrl-C by default)
+    pub fn keys(mut self, keys: TerminalKey) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Delay between sending of interrupt keys (at least 1ms)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    /// Give up when prompt is not recognized during this time
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn zero_interval() {
+        let catch = AutobootCatch::default().interval(Duration::ZERO);
+        assert_eq!(catch.interval, MIN_INTERVAL);
+    }
+}
@@ -0,0 +1,258 @@
+use futures::{Future, FutureExt, StreamExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    framing::Frame, serial_config::SerialConfig, transport::Transport, Error, Result, UBootClient,
+};
+
+/// Baud rates which is tried by detection (most common first)
+pub const BAUD_RATES: [u32; 5] = [115200, 57600, 38400, 19200, 9600];
+
+/// Time to listen console at each rate
+const LISTEN_TIME: Duration = Duration::from_millis(500);
+
+/// Time which U-Boot needs to reconfigure its port after handshake
+const SWITCH_DELAY: Duration = Duration::from_millis(150);
+
+impl UBootClient {
+    /// Detect baud rate of console on local serial port
+    ///
+    /// Each of [`BAUD_RATES`] is tried by sending CR and scoring received text.
+    /// Other line settings are taken from config.
+    pub async fn detect_baud(name: impl AsRef<str>, config: &SerialConfig) -> Result<u32> {
+        let name = name.as_ref();
+        detect_baud_by(&BAUD_RATES, LISTEN_TIME, |rate| async move {
+            Ok(config.baud_rate(rate).open(name)?)
+        })
+        .await
+    }
+
+    /// Switch console to another baud rate
+    ///
+    /// U-Boot is switched by `setenv baudrate` which waits ENTER at new rate,
+    /// then host transport is reopened at new rate and prompt is verified.
+    /// When prompt does not appear device is asked to return to old rate
+    /// and host falls back to old rate too.
+    pub async fn switch_baud(&mut self, rate: u32) -> Result<()> {
+        let session = self.session().await;
+        let options = session.options();
+        let (old_rate, opener) = session
+            .baud_opener()
+            .ok_or_else(|| Error::protocol("Baud rate cannot be changed for this transport"))?;
+        if rate == old_rate {
+            return Ok(());
+        }
+
+        {
+            let frames = session.frames().await?;
+            futures::pin_mut!(frames);
+
+            session
+                .send_cmd(format!("setenv baudrate {}", rate))
+                .await?;
+
+            // U-Boot asks for ENTER at new rate
+            loop {
+                let frame = match tokio::time::timeout(options.command_timeout, frames.next()).await
+                {
+                    Ok(Some(Ok(frame))) => frame,
+                    // lost data does not matter here
+                    Ok(Some(Err(_))) => continue,
+                    Ok(None) => return Err(session.closed_error().into()),
+                    Err(_) => return Err(Error::timeout("Baud rate switching")),
+                };
+                let line = String::from_utf8_lossy(frame.data());
+                match frame {
+                    Frame::Line(_) if line.contains("Switch baudrate") => break,
+                    Frame::Line(_) if line.contains("not supported") => {
+                        return Err(Error::protocol(line.trim()))
+                    }
+                    Frame::Line(_) => (),
+                    Frame::Prompt(_) | Frame::Partial(_) => {
+                        return Err(Error::protocol(format!(
+                            "Baud rate {} bps is not accepted by device",
+                            rate
+                        )))
+                    }
+                }
+            }
+        }
+
+        session
+            .switch_transport(opener(rate), opener(old_rate))
+            .await?;
+        session.update_baud_rate(rate);
+        tokio::time::sleep(SWITCH_DELAY).await;
+
+        if verify_prompt(&session, options.prompt_timeout).await? {
+            return Ok(());
+        }
+
+        // device may still hear us, it waits ENTER at old rate then
+        let _ = session
+            .send_cmd(format!("setenv baudrate {}", old_rate))
+            .await;
+        tokio::time::sleep(SWITCH_DELAY).await;
+
+        let _ = session
+            .switch_transport(opener(old_rate), opener(old_rate))
+            .await;
+        session.update_baud_rate(old_rate);
+        tokio::time::sleep(SWITCH_DELAY).await;
+
+        let restored = verify_prompt(&session, options.prompt_timeout)
+            .await
+            .unwrap_or(false);
+        Err(Error::protocol(if restored {
+            format!("Prompt does not appear at {} bps", rate)
+        } else {
+            format!(
+                "Prompt does not appear at {} bps, device may remain at this rate",
+                rate
+            )
+        }))
+    }
+}
+
+/// Send ENTER and check that prompt appears as readable text
+async fn verify_prompt(client: &UBootClient, timeout: Duration) -> Result<bool> {
+    let frames = client.frames().await?;
+    futures::pin_mut!(frames);
+    let known_prompt = frames.prompt().is_some();
+
+    client.send_cmd("").await?;
+
+    let deadline = tokio::time::sleep(timeout * 2).fuse();
+    futures::pin_mut!(deadline);
+    loop {
+        futures::select! {
+            _ = deadline => return Ok(false),
+            frame = frames.next().fuse() => match frame {
+                Some(Ok(Frame::Prompt(_))) => return Ok(true),
+                Some(Ok(Frame::Partial(data))) if !known_prompt => {
+                    return Ok(text_score(&data) > 0)
+                }
+                Some(_) => (),
+                None => return Err(client.closed_error().into()),
+            },
+        }
+    }
+}
+
+/// Try rates using transports which is opened by function
+pub(crate) async fn detect_baud_by<F, O, T>(rates: &[u32], listen: Duration, open: F) -> Result<u32>
+where
+    F: Fn(u32) -> O,
+    O: Future<Output = Result<T>>,
+    T: Transport + Unpin,
+{
+    let mut best = None;
+
+    for &rate in rates {
+        let mut port = open(rate).await?;
+        // prompt responds to CR, booting device prints anyway
+        let data = talk(&mut port, b"\r", listen).await?;
+
+        let score = text_score(&data);
+        if score > 0 && best.map(|(_, best)| score > best).unwrap_or(true) {
+            best = Some((rate, score));
+        }
+    }
+
+    best.map(|(rate, _)| rate)
+        .ok_or_else(|| Error::not_found("Console baud rate"))
+}
+
+/// Send data and collect response during listen time
+pub(crate) async fn talk<T>(port: &mut T, data: &[u8], listen: Duration) -> Result<Vec<u8>>
+where
+    T: Transport + Unpin,
+{
+    port.write_all(data).await?;
+
+    let mut response = Vec::new();
+    let deadline = Instant::now() + listen;
+    while let Ok(res) = tokio::time::timeout_at(deadline, port.read_buf(&mut response)).await {
+        if res? == 0 {
+            break;
+        }
+    }
+    Ok(response)
+}
+
+/// Score of data as console text (higher is better)
+///
+/// Wrong rate usually gives bytes which is not printable.
+pub(crate) fn text_score(data: &[u8]) -> i32 {
+    const MARKERS: [&[u8]; 5] = [b"U-Boot", b"<INTERRUPT>", b" # ", b"=> ", b"login:"];
+
+    let text: i32 = data
+        .iter()
+        .map(|byte| match byte {
+            b'\r' | b'\n' | b'\t' | 0x20..=0x7e => 1,
+            _ => -4,
+        })
+        .sum();
+    let markers: i32 = MARKERS
+        .iter()
+        .map(|marker| data.windows(marker.len()).filter(|w| w == marker).count() as i32)
+        .sum();
+
+    text + markers * 32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Simulator;
+    use tokio::io::DuplexStream;
+
+    #[test]
+    fn score() {
+        assert_eq!(text_score(b""), 0);
+        assert!(text_score(b"\r\nhisilicon # ") > text_score(b"\r\nhello world!"));
+        assert!(text_score(b"\x80\xfe\x00\x1f\xe0\x8c") < 0);
+    }
+
+    /// Device which talks at 57600 only
+    fn device(rate: u32) -> DuplexStream {
+        let (host, mut device) = tokio::io::duplex(256);
+        tokio::spawn(async move {
+            if rate == 57600 {
+                let _ = Simulator::new().run(device).await;
+                return;
+            }
+            let mut buf = [0u8; 64];
+            while let Ok(len) = device.read(&mut buf).await {
+                if len == 0 || rate == 9600 {
+                    break;
+                }
+                // garbled response
+                let _ = device.write_all(b"\x80\xfe\x00\xf8\x1f\xe0\x8c").await;
+            }
+        });
+        host
+    }
+
+    #[tokio::test]
+    async fn detect_baud() {
+        let rate = detect_baud_by(&BAUD_RATES, Duration::from_millis(100), |rate| async move {
+            Ok(device(rate))
+        })
+        .await
+        .unwrap();
+        assert_eq!(rate, 57600);
+    }
+
+    #[tokio::test]
+    async fn detect_baud_silence() {
+        let result = detect_baud_by(&[9600], Duration::from_millis(50), |rate| async move {
+            Ok(device(rate))
+        })
+        .await;
+        assert!(matches!(result, Err(Error::NotFound { .. })));
+    }
+}
@@ -2,41 +2,145 @@ use core::{
     pin::Pin,
     task::{Context, Poll},
 };
-use std::{borrow::Cow, collections::VecDeque, marker::Unpin};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    marker::Unpin,
+    sync::{Arc, Mutex},
+};
 
 use futures::{select, Future, FutureExt, Stream, StreamExt};
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio_serial as serial;
 
 use crate::{
+    autoboot::AutobootCatch,
+    client_options::ClientOptions,
+    command::{command_line, CommandOutput, Execution},
+    connection::{ConnectionState, TransportError},
     flash_info::{FlashInfo, FlashKind},
+    framing::{Frame, FramesStream},
     hex_dump::HexDump,
     parse_utils,
-    terminal_key::TerminalKey,
+    reconnect::{ReconnectPolicy, Reopen},
+    recorder::{Direction, Recorder},
+    rfc2217::Rfc2217Stream,
+    serial_config::SerialConfig,
+    telnet::{connect_tcp, TelnetStream},
+    terminal_key::{AutobootStop, TerminalKey},
+    transport::{Endpoint, Transport},
     variables::{MemRegion, Variables},
     version_info::VersionInfo,
-    Map, Result,
+    Error, Map, Result,
 };
 
-type Payload = Vec<u8>;
+pub(crate) type Payload = Vec<u8>;
+
+/// Received chunks
+///
+/// Subscriber which falls behind by more than 1024 chunks
+/// receives lag error with number of skipped chunks.
+pub type Chunks = tokio_stream::wrappers::BroadcastStream<Payload>;
+
+/// Connection state changes
+pub type ConnectionEvents = tokio_stream::wrappers::BroadcastStream<ConnectionState>;
+
+/** Boxed transport */
+pub(crate) type BoxTransport = Pin<Box<dyn Transport>>;
+
+/** Transport opener for reconnecting */
+type Opener =
+    Box<dyn Fn() -> futures::future::BoxFuture<'static, Result<BoxTransport>> + Send + Sync>;
+
+/** Transport opener for baud rate switching */
+pub(crate) type BaudOpener =
+    dyn Fn(u32) -> futures::future::BoxFuture<'static, Result<BoxTransport>> + Send + Sync;
+
+/** Number of connection events which is buffered for each subscriber */
+const EVENTS_CAPACITY: usize = 16;
+
+/** Number of received chunks which is buffered for each subscriber */
+const RX_CAPACITY: usize = 1024;
 
 /** Client control message */
-#[derive(Debug)]
 enum CtlMsg {
     /** Output data */
-    Out { payload_data: Payload },
-    /** Subscribe to input */
-    Sub {
-        payload_sender: mpsc::Sender<Payload>,
+    Out {
+        payload_data: Payload,
+        result_sender: oneshot::Sender<core::result::Result<(), TransportError>>,
+    },
+    /** Start or stop recording */
+    Rec { recorder: Option<Recorder> },
+    /** Close transport and open another one (or fallback when it fails) */
+    Switch {
+        open: futures::future::BoxFuture<'static, Result<BoxTransport>>,
+        fallback: futures::future::BoxFuture<'static, Result<BoxTransport>>,
+        result_sender: oneshot::Sender<Result<()>>,
     },
 }
 
-const RX_DELAY: tokio::time::Duration = tokio::time::Duration::from_millis(50);
-const TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_millis(150);
+/** Current baud rate and opener to change it */
+struct BaudSwitch {
+    rate: u32,
+    opener: Arc<BaudOpener>,
+}
+
+/** State shared between client handles */
+#[derive(Default)]
+struct Shared {
+    /** Learned shell prompt */
+    prompt: Mutex<Option<Payload>>,
+    options: Mutex<ClientOptions>,
+    /** Command session lock */
+    session: Arc<tokio::sync::Mutex<()>>,
+    baud: Mutex<Option<BaudSwitch>>,
+}
 
 #[derive(Clone)]
 pub struct UBootClient {
     ctl_tx: mpsc::Sender<CtlMsg>,
+    /** Received data which is published by pump task */
+    rx_tx: broadcast::WeakSender<Payload>,
+    state_rx: watch::Receiver<ConnectionState>,
+    events_tx: broadcast::WeakSender<ConnectionState>,
+    shared: Arc<Shared>,
+    /** Session lock which is held by this handle */
+    session: Option<Arc<tokio::sync::OwnedMutexGuard<()>>>,
+}
+
+/// Exclusive command session
+///
+/// Commands from other client handles are waiting until session is dropped,
+/// passive subscribers like [`UBootClient::chunks`] are not affected.
+pub struct Session {
+    client: UBootClient,
+}
+
+impl core::ops::Deref for Session {
+    type Target = UBootClient;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl core::ops::DerefMut for Session {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+/// Open endpoint transport
+pub(crate) async fn open_transport(
+    endpoint: &Endpoint,
+    config: &SerialConfig,
+) -> Result<BoxTransport> {
+    Ok(match endpoint {
+        Endpoint::Serial(name) => Box::pin(config.open(name)?),
+        Endpoint::Tcp(addr) => Box::pin(connect_tcp(addr).await?),
+        Endpoint::Telnet(addr) => Box::pin(TelnetStream::connect(addr).await?),
+        Endpoint::Rfc2217(addr) => Box::pin(Rfc2217Stream::connect(addr, config).await?),
+    })
 }
 
 impl UBootClient {
@@ -45,136 +149,520 @@ impl UBootClient {
         Ok(ports.into_iter().map(|port| port.port_name).collect())
     }
 
+    /// Open local serial port with 8N1 line settings
     pub fn new<'a>(name: impl Into<Cow<'a, str>>, rate: u32) -> Result<Self> {
-        let builder = serial::new(name, rate)
-            //.data_bits(serial::DataBits::Eight)
-            //.stop_bits(serial::StopBits::One)
-            //.parity(serial::Parity::None)
-            //.flow_control(serial::FlowControl::None)
-            //.timeout(core::time::Duration::from_millis(250))
-            ;
-
-        let mut port = serial::SerialStream::open(&builder)?;
-        port.set_exclusive(true)?;
-        let (mut rx_port, mut tx_port) = tokio::io::split(port);
+        Self::with_config(name, &SerialConfig::new(rate))
+    }
+
+    /// Open local serial port with specified line settings
+    pub fn with_config<'a>(name: impl Into<Cow<'a, str>>, config: &SerialConfig) -> Result<Self> {
+        let name = name.into();
+        let port = config.open(&name)?;
+
+        let client = Self::from_transport(port);
+        client.set_reopen_baud(Arc::new(Reopen::new(
+            &Endpoint::Serial(name.into()),
+            config,
+            false,
+        )));
+        Ok(client)
+    }
+
+    /// Open endpoint
+    ///
+    /// Line settings are applied to local serial and RFC 2217 ports only.
+    pub async fn open(endpoint: &Endpoint, config: &SerialConfig) -> Result<Self> {
+        let client = Self::from_transport(open_transport(endpoint, config).await?);
+        client.set_reopen_baud(Arc::new(Reopen::new(endpoint, config, false)));
+        Ok(client)
+    }
+
+    /// Open endpoint and reopen it according to policy when it fails
+    pub async fn open_reconnecting(
+        endpoint: &Endpoint,
+        config: &SerialConfig,
+        policy: ReconnectPolicy,
+    ) -> Result<Self> {
+        let reopen = Arc::new(Reopen::new(endpoint, config, policy.by_serial_number));
+        let transport = open_transport(endpoint, config).await?;
+
+        let client = Self::from_transport_reconnecting(
+            transport,
+            {
+                let reopen = reopen.clone();
+                move || {
+                    let reopen = reopen.clone();
+                    async move { reopen.open().await }
+                }
+            },
+            policy,
+        );
+        client.set_reopen_baud(reopen);
+        Ok(client)
+    }
 
+    /// Use endpoint reopening for baud rate switching when it is supported
+    fn set_reopen_baud(&self, reopen: Arc<Reopen>) {
+        if let Some(rate) = reopen.baud_rate() {
+            self.set_baud_opener(rate, move |rate| {
+                let reopen = reopen.clone();
+                async move { reopen.open_at(rate).await }
+            });
+        }
+    }
+
+    /// Set function which reopens transport at specified baud rate
+    ///
+    /// It is used by [`UBootClient::switch_baud`]. Clients which is opened
+    /// from local serial or RFC 2217 endpoints have it already.
+    pub fn set_baud_opener<F, O, T>(&self, rate: u32, opener: F)
+    where
+        F: Fn(u32) -> O + Send + Sync + 'static,
+        O: Future<Output = Result<T>> + Send + 'static,
+        T: Transport,
+    {
+        let opener: Arc<BaudOpener> = Arc::new(move |rate| {
+            opener(rate)
+                .map(|result| result.map(|transport| Box::pin(transport) as BoxTransport))
+                .boxed()
+        });
+        *self.shared.baud.lock().unwrap() = Some(BaudSwitch { rate, opener });
+    }
+
+    /// Current baud rate when it can be switched
+    pub fn baud_rate(&self) -> Option<u32> {
+        self.shared
+            .baud
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|baud| baud.rate)
+    }
+
+    pub(crate) fn baud_opener(&self) -> Option<(u32, Arc<BaudOpener>)> {
+        self.shared
+            .baud
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|baud| (baud.rate, baud.opener.clone()))
+    }
+
+    pub(crate) fn update_baud_rate(&self, rate: u32) {
+        if let Some(baud) = &mut *self.shared.baud.lock().unwrap() {
+            baud.rate = rate;
+        }
+    }
+
+    /// Replace transport (received data streams are kept)
+    pub(crate) async fn switch_transport(
+        &self,
+        open: futures::future::BoxFuture<'static, Result<BoxTransport>>,
+        fallback: futures::future::BoxFuture<'static, Result<BoxTransport>>,
+    ) -> Result<()> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.ctl_tx
+            .send(CtlMsg::Switch {
+                open,
+                fallback,
+                result_sender,
+            })
+            .await
+            .map_err(|_| self.closed_error())?;
+        result_receiver.await.map_err(|_| self.closed_error())?
+    }
+
+    /// Create client which uses specified transport
+    pub fn from_transport(transport: impl Transport) -> Self {
+        Self::spawn(Box::pin(transport), None)
+    }
+
+    /// Create client which uses opener to replace failed transport
+    ///
+    /// Streams like [`UBootClient::chunks`] continue receiving data after reconnect.
+    pub fn from_transport_reconnecting<F, O, T>(
+        transport: impl Transport,
+        opener: F,
+        policy: ReconnectPolicy,
+    ) -> Self
+    where
+        F: Fn() -> O + Send + Sync + 'static,
+        O: Future<Output = Result<T>> + Send + 'static,
+        T: Transport,
+    {
+        let opener: Opener = Box::new(move || {
+            opener()
+                .map(|result| result.map(|transport| Box::pin(transport) as BoxTransport))
+                .boxed()
+        });
+        Self::spawn(Box::pin(transport), Some((opener, policy)))
+    }
+
+    fn spawn(transport: BoxTransport, reconnect: Option<(Opener, ReconnectPolicy)>) -> Self {
         let (ctl_tx, mut ctl_rx) = mpsc::channel(1000);
+        let (rx_tx, _) = broadcast::channel(RX_CAPACITY);
+        let rx_weak = rx_tx.downgrade();
+        let (state_tx, state_rx) = watch::channel(ConnectionState::Connected);
+        let (events_tx, _) = broadcast::channel(EVENTS_CAPACITY);
+        let events_weak = events_tx.downgrade();
 
         tokio::spawn(async move {
             use std::io::Cursor;
             use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-            let mut subscribers: slab::Slab<mpsc::Sender<Payload>> = slab::Slab::with_capacity(4);
+            let set_state = |state: ConnectionState| {
+                let _ = events_tx.send(state.clone());
+                let _ = state_tx.send(state);
+            };
+
+            let mut recorder: Option<Recorder> = None;
 
             let rx_lim = 64 << 10;
             let mut rx_buf = Vec::with_capacity(rx_lim);
 
-            loop {
-                select! {
-                    rx_res = rx_port.read_buf(&mut rx_buf).fuse() => {
-                        //eprintln!("!!!! rx: {:?}", std::str::from_utf8(&rx_buf));
-                        /* remove closed receivers */
-                        subscribers.retain(|_, subscriber| !subscriber.is_closed());
-                        match rx_res {
-                            /* receiver error */
-                            Err(_rx_err) => {
-                                break;
-                            },
-                            /* received chunk */
-                            Ok(_rx_len) => {
-                                for (_, payload_sender) in &subscribers {
-                                    let _ = payload_sender.send(rx_buf.clone()).await;
-                                }
-                                rx_buf.clear();
-                            },
+            let mut transport = transport;
+
+            'connection: loop {
+                let (mut rx_port, mut tx_port) = tokio::io::split(transport);
+
+                let exit = loop {
+                    select! {
+                        rx_res = rx_port.read_buf(&mut rx_buf).fuse() => {
+                            //eprintln!("!!!! rx: {:?}", std::str::from_utf8(&rx_buf));
+                            match rx_res {
+                                /* receiver error or end of stream */
+                                Err(rx_err) => break Err(rx_err.into()),
+                                Ok(0) => break Err(TransportError::eof()),
+                                /* received chunk */
+                                Ok(_rx_len) => {
+                                    if let Some(rec) = &mut recorder {
+                                        if rec.record(Direction::Rx, &rx_buf).await.is_err() {
+                                            /* stop recording on error */
+                                            recorder = None;
+                                        }
+                                    }
+                                    /* never waits for subscribers, lagged ones will be notified */
+                                    let _ = rx_tx.send(rx_buf.clone());
+                                    rx_buf.clear();
+                                },
+                            }
+                        },
+                        ctl_evt = ctl_rx.recv().fuse() => if let Some(ctl_evt) = ctl_evt {
+                            match ctl_evt {
+                                CtlMsg::Out {payload_data, result_sender} => {
+                                    if let Some(rec) = &mut recorder {
+                                        if rec.record(Direction::Tx, &payload_data).await.is_err() {
+                                            /* stop recording on error */
+                                            recorder = None;
+                                        }
+                                    }
+                                    let mut cursor = Cursor::new(&payload_data);
+                                    let tx_res = match tx_port.write_all_buf(&mut cursor).await {
+                                        Ok(()) => tx_port.flush().await,
+                                        Err(tx_err) => Err(tx_err),
+                                    };
+                                    match tx_res {
+                                        Ok(()) => {
+                                            let _ = result_sender.send(Ok(()));
+                                        },
+                                        /* transmitter error */
+                                        Err(tx_err) => {
+                                            let tx_err = TransportError::from(tx_err);
+                                            let _ = result_sender.send(Err(tx_err.clone()));
+                                            break Err(tx_err);
+                                        },
+                                    }
+                                },
+                                CtlMsg::Rec {recorder: new_recorder} => {
+                                    recorder = new_recorder;
+                                },
+                                CtlMsg::Switch {open, fallback, result_sender} => {
+                                    break Ok((open, fallback, result_sender));
+                                },
+                            }
+                        } else {
+                            /* handle closed */
+                            break 'connection;
+                        },
+                    }
+                };
+
+                let error = match exit {
+                    Err(error) => error,
+                    Ok((open, fallback, result_sender)) => {
+                        /* port must be closed before reopening */
+                        drop((rx_port, tx_port));
+                        rx_buf.clear();
+                        let (result, opened) = match open.await {
+                            Ok(opened) => (Ok(()), Ok(opened)),
+                            Err(open_err) => (Err(open_err), fallback.await),
+                        };
+                        let _ = result_sender.send(result);
+                        match opened {
+                            Ok(opened) => {
+                                transport = opened;
+                                continue 'connection;
+                            }
+                            Err(open_err) => std::io::Error::other(open_err.to_string()).into(),
                         }
-                    },
-                    ctl_evt = ctl_rx.recv().fuse() => if let Some(ctl_evt) = ctl_evt {
-                        match ctl_evt {
-                            CtlMsg::Out {payload_data} => {
-                                let mut cursor = Cursor::new(&payload_data);
-                                let _ = tx_port.write_all_buf(&mut cursor).await.map_err(|tx_err| tx_err.to_string());
-                            },
-                            CtlMsg::Sub {payload_sender} => {
-                                /* remove closed receivers */
-                                subscribers.retain(|_, subscriber| !subscriber.is_closed());
-                                subscribers.insert(payload_sender);
+                    }
+                };
+
+                let (opener, policy) = match &reconnect {
+                    Some(reconnect) => reconnect,
+                    None => {
+                        set_state(ConnectionState::Disconnected(error));
+                        break;
+                    }
+                };
+
+                let mut attempt = 0;
+
+                transport = loop {
+                    attempt += 1;
+                    if policy.exhausted(attempt) {
+                        set_state(ConnectionState::Disconnected(error));
+                        break 'connection;
+                    }
+                    set_state(ConnectionState::Reconnecting {
+                        attempt,
+                        error: error.clone(),
+                    });
+
+                    let delay = tokio::time::sleep(policy.delay(attempt)).fuse();
+                    futures::pin_mut!(delay);
+
+                    loop {
+                        select! {
+                            _ = delay => break,
+                            ctl_evt = ctl_rx.recv().fuse() => match ctl_evt {
+                                /* nothing can be sent until reconnected */
+                                Some(CtlMsg::Out {result_sender, ..}) => {
+                                    let _ = result_sender.send(Err(error.clone()));
+                                },
+                                Some(CtlMsg::Rec {recorder: new_recorder}) => {
+                                    recorder = new_recorder;
+                                },
+                                Some(CtlMsg::Switch {result_sender, ..}) => {
+                                    let _ = result_sender.send(Err(error.clone().into()));
+                                },
+                                /* handle closed */
+                                None => break 'connection,
                             },
                         }
-                    } else {
-                        /* handle closed */
-                        break;
-                    },
-                }
+                    }
+
+                    if let Ok(transport) = opener().await {
+                        break transport;
+                    }
+                };
+
+                rx_buf.clear();
+                set_state(ConnectionState::Connected);
             }
         });
 
-        Ok(Self { ctl_tx })
+        Self {
+            ctl_tx,
+            rx_tx: rx_weak,
+            state_rx,
+            events_tx: events_weak,
+            shared: Default::default(),
+            session: None,
+        }
+    }
+
+    /// Start exclusive command session
+    ///
+    /// Nested sessions of same handle does not wait.
+    pub async fn session(&self) -> Session {
+        let guard = match &self.session {
+            Some(guard) => guard.clone(),
+            None => Arc::new(self.shared.session.clone().lock_owned().await),
+        };
+        let mut client = self.clone();
+        client.session = Some(guard);
+        Session { client }
+    }
+
+    /// Current connection state
+    pub fn state(&self) -> ConnectionState {
+        self.state_rx.borrow().clone()
+    }
+
+    /// Watch connection state changes
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state_rx.clone()
+    }
+
+    /// Receive connection state changes including reconnect attempts
+    pub fn events(&self) -> ConnectionEvents {
+        let events_rx = match self.events_tx.upgrade() {
+            Some(events_tx) => events_tx.subscribe(),
+            /* no more events */
+            None => broadcast::channel(1).1,
+        };
+        ConnectionEvents::new(events_rx)
+    }
+
+    /// Error which caused disconnection
+    pub(crate) fn closed_error(&self) -> TransportError {
+        self.state()
+            .error()
+            .cloned()
+            .unwrap_or_else(TransportError::closed)
     }
 
     /// Send raw data
-    pub async fn send_raw(&self, data: impl Into<Payload>) -> Result<()> {
+    ///
+    /// Waits until data is written to transport.
+    pub async fn send_raw(
+        &self,
+        data: impl Into<Payload>,
+    ) -> core::result::Result<(), TransportError> {
+        let (result_sender, result_receiver) = oneshot::channel();
         self.ctl_tx
             .send(CtlMsg::Out {
                 payload_data: data.into(),
+                result_sender,
             })
-            .await?;
+            .await
+            .map_err(|_| self.closed_error())?;
+        result_receiver.await.map_err(|_| self.closed_error())?
+    }
+
+    /// Record console traffic
+    pub async fn record(&self, recorder: Recorder) -> Result<()> {
+        self.ctl_tx
+            .send(CtlMsg::Rec {
+                recorder: Some(recorder),
+            })
+            .await
+            .map_err(|_| self.closed_error())?;
+        Ok(())
+    }
+
+    /// Stop recording console traffic
+    pub async fn stop_recording(&self) -> Result<()> {
+        self.ctl_tx
+            .send(CtlMsg::Rec { recorder: None })
+            .await
+            .map_err(|_| self.closed_error())?;
         Ok(())
     }
 
     /// Send command line
-    pub async fn send_cmd(&self, cmd: impl Into<String>) -> Result<()> {
+    pub async fn send_cmd(
+        &self,
+        cmd: impl Into<String>,
+    ) -> core::result::Result<(), TransportError> {
         let mut cmd = cmd.into();
         cmd.push('\r');
         self.send_raw(cmd).await
     }
 
     /// Receive raw chunks
-    pub async fn chunks(&self) -> Result<tokio_stream::wrappers::ReceiverStream<Payload>> {
-        let (tx, rx) = mpsc::channel(1000);
-        self.ctl_tx.send(CtlMsg::Sub { payload_sender: tx }).await?;
-        Ok(tokio_stream::wrappers::ReceiverStream::new(rx))
+    pub async fn chunks(&self) -> Result<Chunks> {
+        let rx_tx = self.rx_tx.upgrade().ok_or_else(|| self.closed_error())?;
+        Ok(Chunks::new(rx_tx.subscribe()))
     }
 
     /// Receive lines
-    pub async fn lines(
-        &self,
-    ) -> Result<LinesStream<tokio_stream::wrappers::ReceiverStream<Payload>>> {
-        Ok(LinesStream::new(self.chunks().await?, RX_DELAY))
+    pub async fn lines(&self) -> Result<LinesStream<Chunks>> {
+        Ok(LinesStream::new(
+            self.chunks().await?,
+            self.options().idle_timeout,
+        ))
+    }
+
+    /// Receive frames
+    pub async fn frames(&self) -> Result<FramesStream<Chunks>> {
+        Ok(FramesStream::new(
+            self.chunks().await?,
+            self.options().idle_timeout,
+            self.prompt(),
+        ))
+    }
+
+    /// Shell prompt which is learned by [`UBootClient::shell_presence`]
+    pub fn prompt(&self) -> Option<Payload> {
+        self.shared.prompt.lock().unwrap().clone()
+    }
+
+    /// Set shell prompt when it is known in advance
+    pub fn set_prompt(&self, prompt: impl Into<Payload>) {
+        *self.shared.prompt.lock().unwrap() = Some(prompt.into());
+    }
+
+    /// Timeouts
+    pub fn options(&self) -> ClientOptions {
+        *self.shared.options.lock().unwrap()
+    }
+
+    /// Set timeouts
+    pub fn set_options(&self, options: ClientOptions) {
+        *self.shared.options.lock().unwrap() = options;
     }
 
     /// Awaiting shell prompt and optionally stop autoboot
     pub async fn shell_presence(&mut self) -> Result<Payload> {
-        self.send_raw(TerminalKey::Ctrl(b'C').encode().unwrap())
+        self.shell_presence_with(&[]).await
+    }
+
+    /// Awaiting shell prompt and stop autoboot using additional rules
+    ///
+    /// Rules are checked before builtin recognition of autoboot hints.
+    pub async fn shell_presence_with(&mut self, stops: &[AutobootStop]) -> Result<Payload> {
+        let session = self.session().await;
+        let timeout = session.options().prompt_timeout;
+        session
+            .send_raw(TerminalKey::Ctrl(b'C').encode().unwrap())
             .await?;
 
-        let lines = self.lines().await?;
-        futures::pin_mut!(lines);
+        let frames = session.frames().await?;
+        futures::pin_mut!(frames);
 
         // try send empty command to get prompt
-        self.send_cmd("").await?;
+        session.send_cmd("").await?;
 
         // try get shell prompt
         loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if !line.ends_with(b"\r") {
-                        return Ok(line);
-                    }
+            match tokio::time::timeout(timeout, frames.next()).await {
+                Ok(Some(Ok(Frame::Prompt(prompt) | Frame::Partial(prompt)))) => {
+                    session.set_prompt(prompt.clone());
+                    return Ok(prompt);
                 }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
+                // lost data does not matter here
+                Ok(Some(Ok(Frame::Line(_)) | Err(_))) => (),
+                Ok(None) => return Err(session.closed_error().into()),
                 Err(_) => break,
             }
         }
 
-        while let Some(line) = lines.next().await {
-            if let Ok(line) = core::str::from_utf8(&line) {
+        while let Some(frame) = frames.next().await {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(_) => continue,
+            };
+            if let Frame::Prompt(prompt) = frame {
+                return Ok(prompt);
+            }
+            if let Ok(line) = core::str::from_utf8(frame.data()) {
                 //eprintln!("rx: {:?}", line);
-                self.send_raw(TerminalKey::Ctrl(b'C').encode().unwrap())
+                session
+                    .send_raw(TerminalKey::Ctrl(b'C').encode().unwrap())
                     .await?;
-                if let Ok(key) = TerminalKey::parse_stop_autoboot(&line) {
+                if let Some(key) = stops
+                    .iter()
+                    .find(|stop| stop.matches(line))
+                    .map(|stop| stop.keys.clone())
+                    .or_else(|| TerminalKey::parse_stop_autoboot(line).ok())
+                {
                     eprintln!("prevent autoboot!");
-                    self.send_raw(key.encode()?).await?;
+                    // Ctrl-C which is sent above already stops it by any key
+                    if key != TerminalKey::Any {
+                        session.send_raw(key.encode()?).await?;
+                    }
                     break;
                 }
             }
@@ -182,88 +670,163 @@ impl UBootClient {
 
         // try get shell prompt
         loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if !line.ends_with(b"\r") {
-                        return Ok(line);
-                    }
+            match tokio::time::timeout(timeout, frames.next()).await {
+                Ok(Some(Ok(Frame::Prompt(prompt) | Frame::Partial(prompt)))) => {
+                    session.set_prompt(prompt.clone());
+                    return Ok(prompt);
                 }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => anyhow::bail!("Prompt await timeout"),
+                // lost data does not matter here
+                Ok(Some(Ok(Frame::Line(_)) | Err(_))) => (),
+                Ok(None) => return Err(session.closed_error().into()),
+                Err(_) => return Err(Error::timeout("Prompt await")),
             }
         }
     }
 
-    /// Get U-Boot version
-    pub async fn get_version(&mut self) -> Result<VersionInfo> {
-        let lines = self.lines().await?;
-        futures::pin_mut!(lines);
-
-        self.send_cmd("getinfo version").await?;
+    /// Catch autoboot by repeating interrupt keys until shell prompt is recognized
+    ///
+    /// Should be started right after power-on. Keys are repeated until known prompt appears,
+    /// U-Boot reports `<INTERRUPT>` or current line looks like prompt (possibly with echoed keys),
+    /// then prompt is awaited as usual.
+    pub async fn catch_autoboot(&mut self, catch: &AutobootCatch) -> Result<Payload> {
+        let session = self.session().await;
+        let timeout = session.options().prompt_timeout;
+        let keys = catch.keys.encode()?;
+
+        let frames = session.frames().await?;
+        futures::pin_mut!(frames);
+        // prompt may be unknown, so current line is inspected too
+        let mut chunks = session.chunks().await?;
+        let mut line = Payload::new();
+
+        let deadline = tokio::time::sleep(catch.deadline).fuse();
+        futures::pin_mut!(deadline);
+        let mut interval = tokio::time::interval(catch.interval);
 
         loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if line.ends_with(b"\r") {
-                        if let Ok(line) = core::str::from_utf8(&line) {
-                            //eprintln!(">> {:?}", line);
-                            if let Ok(version) = VersionInfo::parse(&line) {
-                                return Ok(version);
+            // repeat keys until shell reacts
+            loop {
+                select! {
+                    _ = deadline => return Err(Error::timeout("Autoboot catch")),
+                    _ = interval.tick().fuse() => session.send_raw(keys.clone()).await?,
+                    frame = frames.next().fuse() => match frame {
+                        Some(Ok(Frame::Prompt(prompt))) => return Ok(prompt),
+                        Some(Ok(frame)) if frame.data().ends_with(b"<INTERRUPT>") => break,
+                        // lost data does not matter here
+                        Some(_) => (),
+                        None => return Err(session.closed_error().into()),
+                    },
+                    chunk = chunks.next().fuse() => match chunk {
+                        Some(Ok(chunk)) => {
+                            line.extend(chunk);
+                            if let Some(end) = line.iter().rposition(|b| *b == b'\n') {
+                                line.drain(..=end);
+                            }
+                            if shell_answers(&line, keys.as_bytes()) {
+                                break;
                             }
                         }
-                    }
+                        Some(Err(_)) => (),
+                        None => return Err(session.closed_error().into()),
+                    },
                 }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => anyhow::bail!("Version request timeout"),
             }
-        }
-    }
+            line.clear();
 
-    /// Get flash info
-    pub async fn get_flash_info(&mut self) -> Result<FlashInfo> {
-        let lines = self.lines().await?;
-        futures::pin_mut!(lines);
-
-        self.send_cmd("getinfo bootmode").await?;
-
-        let kind = loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if line.ends_with(b"\r") {
-                        if let Ok(line) = core::str::from_utf8(&line) {
-                            //eprintln!(">> {:?}", line);
-                            if let Ok(kind) = FlashKind::parse(&line) {
-                                break kind;
-                            }
+            // clear line which may be filled by keys and try get shell prompt
+            session
+                .send_raw(TerminalKey::Ctrl(b'C').encode().unwrap())
+                .await?;
+            session.send_cmd("").await?;
+            loop {
+                select! {
+                    _ = deadline => return Err(Error::timeout("Autoboot catch")),
+                    frame = tokio::time::timeout(timeout, frames.next()).fuse() => match frame {
+                        Ok(Some(Ok(Frame::Prompt(prompt) | Frame::Partial(prompt)))) => {
+                            session.set_prompt(prompt.clone());
+                            return Ok(prompt);
                         }
-                    }
+                        Ok(Some(Ok(Frame::Line(_)) | Err(_))) => (),
+                        Ok(None) => return Err(session.closed_error().into()),
+                        // still booting, so continue
+                        Err(_) => break,
+                    },
                 }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => anyhow::bail!("Version info timeout"),
             }
-        };
+        }
+    }
 
-        self.send_cmd(match kind {
-            FlashKind::Spi => "getinfo spi",
-            FlashKind::Nand => "getinfo nand",
-        })
-        .await?;
+    /// Start command execution
+    pub async fn execute(&self, cmd: impl Into<String>) -> Result<Execution> {
+        self.execute_timeout(cmd, self.options().command_timeout)
+            .await
+    }
 
-        let mut info = FlashInfo::from_kind(kind);
+    /// Start command execution with specific command timeout
+    pub async fn execute_timeout(
+        &self,
+        cmd: impl Into<String>,
+        timeout: tokio::time::Duration,
+    ) -> Result<Execution> {
+        let cmd = cmd.into();
+        let session = self.session().await;
+        let frames = session.frames().await?;
+        session.send_cmd(command_line(&cmd)).await?;
+        Ok(Execution::new(
+            cmd,
+            frames,
+            timeout,
+            session.options().prompt_timeout,
+            session,
+        ))
+    }
 
-        loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if line.ends_with(b"\r") {
-                        if let Ok(line) = core::str::from_utf8(&line) {
-                            //eprintln!(">> {:?}", line);
-                            let _ = info.fill_parse(&line);
-                        }
-                    }
-                }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => break,
-            }
+    /// Run command and capture its output
+    pub async fn run(&self, cmd: impl Into<String>) -> Result<CommandOutput> {
+        self.execute(cmd).await?.output().await
+    }
+
+    /// Run command with specific command timeout
+    pub async fn run_timeout(
+        &self,
+        cmd: impl Into<String>,
+        timeout: tokio::time::Duration,
+    ) -> Result<CommandOutput> {
+        self.execute_timeout(cmd, timeout).await?.output().await
+    }
+
+    /// Get U-Boot version
+    pub async fn get_version(&mut self) -> Result<VersionInfo> {
+        let output = self.run("getinfo version").await?.check()?;
+        output
+            .lines
+            .iter()
+            .find_map(|line| VersionInfo::parse(line).ok())
+            .ok_or_else(|| Error::not_found("Version info"))
+    }
+
+    /// Get flash info
+    pub async fn get_flash_info(&mut self) -> Result<FlashInfo> {
+        let session = self.session().await;
+
+        let output = session.run("getinfo bootmode").await?.check()?;
+        let kind = output
+            .lines
+            .iter()
+            .find_map(|line| FlashKind::parse(line).ok())
+            .ok_or_else(|| Error::not_found("Boot mode"))?;
+
+        let output = session
+            .run(match kind {
+                FlashKind::Spi => "getinfo spi",
+                FlashKind::Nand => "getinfo nand",
+            })
+            .await?
+            .check()?;
+
+        let mut info = FlashInfo::from_kind(kind);
+        for line in &output.lines {
+            let _ = info.fill_parse(line);
         }
 
         Ok(info)
@@ -271,51 +834,21 @@ impl UBootClient {
 
     /// Get environment
     pub async fn get_environ(&mut self) -> Result<Variables> {
-        let lines = self.lines().await?;
-        futures::pin_mut!(lines);
-
-        self.send_cmd("printenv").await?;
+        let output = self.run("printenv").await?.check()?;
         let mut vars = Variables::default();
-
-        loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if line.ends_with(b"\r") {
-                        if let Ok(line) = core::str::from_utf8(&line) {
-                            let _ = vars.extend_parse_env(&line);
-                        }
-                    }
-                }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => break,
-            }
+        for line in &output.lines {
+            let _ = vars.extend_parse_env(line);
         }
-
         Ok(vars)
     }
 
     /// Get board info
     pub async fn get_bdinfo(&mut self) -> Result<Variables> {
-        let lines = self.lines().await?;
-        futures::pin_mut!(lines);
-
-        self.send_cmd("bdinfo").await?;
+        let output = self.run("bdinfo").await?.check()?;
         let mut vars = Variables::default();
-
-        loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if line.ends_with(b"\r") {
-                        if let Ok(line) = core::str::from_utf8(&line) {
-                            let _ = vars.extend_parse_env(&line);
-                        }
-                    }
-                }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => break,
-            }
+        for line in &output.lines {
+            let _ = vars.extend_parse_env(line);
         }
-
         Ok(vars)
     }
 
@@ -330,156 +863,144 @@ impl UBootClient {
         let environ = self.get_environ().await?;
         let bootargs = environ
             .get("bootargs")
-            .ok_or_else(|| anyhow::anyhow!("Bootargs for found in environment"))?;
+            .ok_or_else(|| Error::not_found("Bootargs"))?;
         let mut iter = bootargs.splitn(2, "mtdparts=");
         match (iter.next(), iter.next()) {
             (Some(_), Some(args)) => Variables::parse_mtd_parts(args),
-            _ => Err(anyhow::anyhow!("No mtdparts found in bootargs")),
+            _ => Err(Error::not_found("Mtdparts in bootargs")),
         }
     }
 
     /// Send SPI flash command (sf)
     pub async fn spi_flash_cmd(&mut self, cmd: impl AsRef<str>) -> Result<()> {
-        let lines = self.lines().await?;
-        futures::pin_mut!(lines);
-
-        self.send_cmd(format!("sf {}", cmd.as_ref())).await?;
-        let mut count = 0;
-
-        loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if line.ends_with(b"\r") {
-                        if let Ok(_line) = core::str::from_utf8(&line) {
-                            //println!(">> {}", _line);
-                            count += 1;
-                        }
-                    }
-                }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => break,
-            }
-        }
+        let output = self.run(format!("sf {}", cmd.as_ref())).await?.check()?;
 
-        if count > 0 {
+        if output.code.is_some() || !output.lines.is_empty() {
             Ok(())
         } else {
-            Err(anyhow::anyhow!("Unable to execute SPI command"))
+            Err(Error::protocol("Unable to execute SPI command"))
         }
     }
 
     /// Read MTD part to RAM
     pub async fn read_mtd_part(&mut self, region: &MemRegion, address: u64) -> Result<()> {
-        self.spi_flash_cmd("probe 0").await?;
-        self.spi_flash_cmd(format!(
-            "read {:#08x} {:#08x} {:#08x}",
-            address, region.base, region.size
-        ))
-        .await?;
+        let mut session = self.session().await;
+
+        session.spi_flash_cmd("probe 0").await?;
+        session
+            .run_timeout(
+                format!(
+                    "sf read {:#08x} {:#08x} {:#08x}",
+                    address, region.base, region.size
+                ),
+                session.options().data_timeout(region.size),
+            )
+            .await?
+            .check()?;
 
         Ok(())
     }
 
     /// Calculate CRC32 of memory region
     pub async fn calc_crc32(&mut self, address: u64, size: u64) -> Result<u32> {
-        let lines = self.lines().await?;
-        futures::pin_mut!(lines);
-
-        self.send_cmd(format!("crc32 {:#08x} {:#08x}", address, size))
-            .await?;
-
-        loop {
-            match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if line.ends_with(b"\r") {
-                        let line = core::str::from_utf8(&line)?;
-                        if line.starts_with("crc32 for") {
-                            if let Some(sum) = line.rsplitn(2, ' ').next() {
-                                let (_, sum) = parse_utils::hex_u64(sum).map_err(|err| {
-                                    anyhow::anyhow!("Unable to parse crc32: {}", err)
-                                })?;
-                                return Ok(sum as _);
-                            }
-                        }
-                    }
-                }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => anyhow::bail!("Calc CRC32 timeout"),
-            }
-        }
+        let output = self
+            .run_timeout(
+                format!("crc32 {:#08x} {:#08x}", address, size),
+                self.options().data_timeout(size),
+            )
+            .await?
+            .check()?;
+
+        let line = output
+            .lines
+            .iter()
+            .find(|line| line.starts_with("crc32 for"))
+            .ok_or_else(|| Error::not_found("CRC32"))?;
+        let sum = line.rsplit(' ').next().unwrap_or_default();
+        let (_, sum) = parse_utils::hex_u64(sum).map_err(|_| Error::parse("crc32", sum))?;
+        Ok(sum as _)
     }
 
     /// Dump MTD part in text mode (slow)
     pub async fn dump_mtd_part(
         &mut self,
-        //mut file: impl AsyncWrite + Unpin,
         mut file: impl tokio::io::AsyncWrite + Unpin,
         region: &MemRegion,
         address: u64,
         progress: mpsc::Sender<u64>,
     ) -> Result<()> {
-        //use futures::AsyncWriteExt;
         use tokio::io::AsyncWriteExt;
 
-        self.read_mtd_part(region, address).await?;
-        let checksum = self.calc_crc32(address, region.size).await?;
+        let mut session = self.session().await;
 
-        let lines = self.lines().await?;
-        futures::pin_mut!(lines);
+        session.read_mtd_part(region, address).await?;
+        let checksum = session.calc_crc32(address, region.size).await?;
 
-        self.send_cmd(format!("md.b {:#08x} {:#08x}", address, region.size))
+        let mut exec = session
+            .execute(format!("md.b {:#08x} {:#08x}", address, region.size))
             .await?;
 
         let mut hasher = crc32fast::Hasher::new();
         let mut off = 0;
 
         while off < region.size {
-            let data = match tokio::time::timeout(TIMEOUT, lines.next()).await {
-                Ok(Some(line)) => {
-                    if line.ends_with(b"\r") {
-                        let line = core::str::from_utf8(&line)?;
-                        if line.starts_with("md.b") {
-                            continue;
-                        }
-                        HexDump::parse_line(&line)?
-                    } else {
-                        anyhow::bail!("Unexpected end of dump");
-                    }
-                }
-                Ok(None) => anyhow::bail!("Unexpected EOF"),
-                Err(_) => anyhow::bail!("Dump memory timeout"),
+            let data = match exec.next_line().await? {
+                Some(line) => HexDump::parse_line(line)?,
+                None => return Err(Error::Eof),
             };
 
             if data.len() > 16 {
-                anyhow::bail!(
+                return Err(Error::protocol(format!(
                     "Number of bytes per line unexpectedly exceeds 16: {}",
                     data.len()
-                );
+                )));
             }
 
-            hasher.update(&*data);
-            file.write_all(&*data).await?;
+            hasher.update(&data);
+            file.write_all(&data).await?;
             off += data.len() as u64;
 
-            if let Err(_) = progress.send(off).await {
-                self.send_raw(TerminalKey::Ctrl(b'C').encode().unwrap())
+            if progress.send(off).await.is_err() {
+                session
+                    .send_raw(TerminalKey::Ctrl(b'C').encode().unwrap())
                     .await?;
                 break;
             }
         }
 
         if off > region.size {
-            anyhow::bail!("Out of region by {} bytes", off - region.size);
+            return Err(Error::protocol(format!(
+                "Out of region by {} bytes",
+                off - region.size
+            )));
         }
 
-        if checksum != hasher.finalize() {
-            anyhow::bail!("Checksum does not matches!");
+        let actual = hasher.finalize();
+        if checksum != actual {
+            return Err(Error::ChecksumMismatch {
+                expected: checksum,
+                actual,
+            });
         }
 
         Ok(())
     }
 }
 
+/// Current line looks like shell prompt which is possibly followed by echoed keys
+fn shell_answers(line: &[u8], keys: &[u8]) -> bool {
+    let mut line = line;
+    // autoboot countdown does not echo input
+    if !keys.is_empty() && keys.iter().all(|b| (0x20..0x7f).contains(b)) {
+        while let Some(rest) = line.strip_suffix(keys) {
+            line = rest;
+        }
+    }
+    [b"# ", b"> ", b"$ "]
+        .iter()
+        .any(|tail| line.ends_with(*tail))
+}
+
 pin_project_lite::pin_project! {
     pub struct LinesStream<S> {
         #[pin]
@@ -504,18 +1025,18 @@ impl<S> LinesStream<S> {
     }
 }
 
-impl<S> Stream for LinesStream<S>
+impl<S, E> Stream for LinesStream<S>
 where
-    S: Stream<Item = Payload>,
+    S: Stream<Item = core::result::Result<Payload, E>>,
 {
-    type Item = Payload;
+    type Item = core::result::Result<Payload, E>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
 
         // keep last chunk, return other
         if this.leftover.len() > 1 {
-            return Poll::Ready(this.leftover.pop_front());
+            return Poll::Ready(this.leftover.pop_front().map(Ok));
         }
 
         let stream_poll = this.stream.poll_next(cx);
@@ -524,7 +1045,12 @@ where
         //eprintln!("poll: {:?} {:?}", stream_poll, timer_poll);
 
         match stream_poll {
-            Poll::Ready(Some(chunk)) => {
+            // data lost, so lines is broken
+            Poll::Ready(Some(Err(error))) => {
+                this.leftover.clear();
+                return Poll::Ready(Some(Err(error)));
+            }
+            Poll::Ready(Some(Ok(chunk))) => {
                 // reset timer
                 this.sleep
                     .reset(tokio::time::Instant::now() + *this.timeout);
@@ -547,29 +1073,178 @@ where
                     }
                     // keep last chunk, return other
                     if this.leftover.len() > 1 {
-                        return Poll::Ready(this.leftover.pop_front());
+                        return Poll::Ready(this.leftover.pop_front().map(Ok));
                     }
                 }
             }
             // end of stream
             Poll::Ready(None) => {
                 // return last keeped chunk
-                return Poll::Ready(this.leftover.pop_front());
+                return Poll::Ready(this.leftover.pop_front().map(Ok));
             }
             _ => (),
         }
 
-        match timer_poll {
-            // timeout reached
-            Poll::Ready(_) => {
-                // return last keeped chunk
-                if !this.leftover.is_empty() {
-                    return Poll::Ready(this.leftover.pop_front());
+        // timeout reached
+        if timer_poll.is_ready() && !this.leftover.is_empty() {
+            // return last keeped chunk
+            return Poll::Ready(this.leftover.pop_front().map(Ok));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn shell_answers_prompt() {
+        assert!(shell_answers(b"=> ", b"\x1b\x1b"));
+        assert!(shell_answers(b"hisilicon # stopstop", b"stop"));
+        assert!(!shell_answers(b"hisilicon # st", b"stop"));
+        assert!(!shell_answers(
+            b"Hit any key to stop autoboot:  1 ",
+            b"\x03"
+        ));
+        assert!(!shell_answers(b"Loading: #####", b"\x03"));
+    }
+
+    #[tokio::test]
+    async fn in_memory_transport() {
+        let (host, mut device) = tokio::io::duplex(64);
+        let client = UBootClient::from_transport(host);
+
+        let chunks = client.chunks().await.unwrap();
+        futures::pin_mut!(chunks);
+
+        client.send_cmd("version").await.unwrap();
+        let mut buf = [0u8; 8];
+        device.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"version\r");
+
+        device.write_all(b"U-Boot 2016.11\r\n").await.unwrap();
+        assert_eq!(chunks.next().await.unwrap().unwrap(), b"U-Boot 2016.11\r\n");
+    }
+
+    #[tokio::test]
+    async fn lagged_subscriber() {
+        use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+
+        let (host, mut device) = tokio::io::duplex(64);
+        let client = UBootClient::from_transport(host);
+
+        let slow = client.chunks().await.unwrap();
+        let fast = client.chunks().await.unwrap();
+        futures::pin_mut!(slow, fast);
+
+        // slow subscriber does not stall others
+        for n in 0..RX_CAPACITY + 10 {
+            let chunk = format!("{}\n", n);
+            device.write_all(chunk.as_bytes()).await.unwrap();
+            assert_eq!(fast.next().await.unwrap().unwrap(), chunk.as_bytes());
+        }
+
+        assert!(matches!(
+            slow.next().await.unwrap(),
+            Err(BroadcastStreamRecvError::Lagged(10))
+        ));
+        assert_eq!(slow.next().await.unwrap().unwrap(), b"10\n");
+    }
+
+    #[tokio::test]
+    async fn disconnect() {
+        let (host, device) = tokio::io::duplex(64);
+        let client = UBootClient::from_transport(host);
+        assert!(client.state().is_connected());
+
+        let mut exec = client.execute("version").await.unwrap();
+        let mut state = client.watch_state();
+        drop(device);
+
+        let error = match exec.next_line().await {
+            Err(Error::Transport(error)) => error,
+            other => panic!("Unexpected result: {:?}", other),
+        };
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        state.wait_for(|state| !state.is_connected()).await.unwrap();
+        let error = client.send_raw(b"\r".to_vec()).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn reconnect() {
+        use crate::reconnect::ReconnectPolicy;
+        use tokio::time::Duration;
+
+        let (host, device) = tokio::io::duplex(64);
+        let (next_host, mut next_device) = tokio::io::duplex(64);
+        let hosts = Arc::new(Mutex::new(vec![next_host]));
+
+        let policy = ReconnectPolicy::default()
+            .initial_delay(Duration::from_millis(10))
+            .max_attempts(2);
+        let client = UBootClient::from_transport_reconnecting(
+            host,
+            move || {
+                let host = hosts.lock().unwrap().pop();
+                async move {
+                    host.ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound).into())
                 }
+            },
+            policy,
+        );
+
+        let chunks = client.chunks().await.unwrap();
+        let events = client.events();
+        futures::pin_mut!(chunks, events);
+
+        drop(device);
+        assert!(matches!(
+            events.next().await.unwrap().unwrap(),
+            ConnectionState::Reconnecting { attempt: 1, .. }
+        ));
+        assert!(events.next().await.unwrap().unwrap().is_connected());
+
+        // existing subscribers receive data from new transport
+        next_device.write_all(b"=> ").await.unwrap();
+        assert_eq!(chunks.next().await.unwrap().unwrap(), b"=> ");
+        client.send_cmd("version").await.unwrap();
+        let mut buf = [0u8; 8];
+        next_device.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"version\r");
+
+        // give up when no more transports
+        drop(next_device);
+        for attempt in 1..=2 {
+            match events.next().await.unwrap().unwrap() {
+                ConnectionState::Reconnecting { attempt: n, .. } => assert_eq!(n, attempt),
+                state => panic!("Unexpected state: {:?}", state),
             }
-            _ => (),
         }
+        assert!(matches!(
+            events.next().await.unwrap().unwrap(),
+            ConnectionState::Disconnected(_)
+        ));
+        assert!(events.next().await.is_none());
+    }
 
-        Poll::Pending
+    #[tokio::test]
+    async fn tcp_transport() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = Endpoint::Tcp(listener.local_addr().unwrap().to_string());
+
+        let client = UBootClient::open(&endpoint, &SerialConfig::default())
+            .await
+            .unwrap();
+        let (mut device, _) = listener.accept().await.unwrap();
+
+        client.send_cmd("printenv").await.unwrap();
+        let mut buf = [0u8; 9];
+        device.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"printenv\r");
     }
 }
@@ -0,0 +1,77 @@
+use tokio::time::Duration;
+
+/// Client timeouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientOptions {
+    pub(crate) idle_timeout: Duration,
+    pub(crate) prompt_timeout: Duration,
+    pub(crate) command_timeout: Duration,
+    pub(crate) timeout_per_mib: Duration,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_millis(50),
+            prompt_timeout: Duration::from_millis(150),
+            command_timeout: Duration::from_secs(10),
+            timeout_per_mib: Duration::from_secs(2),
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Silence after which incomplete line is considered as partial
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Time to wait prompt after empty command or return code
+    pub fn prompt_timeout(mut self, timeout: Duration) -> Self {
+        self.prompt_timeout = timeout;
+        self
+    }
+
+    /// Maximum silence while command is running
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// Additional time per MiB of data for flash read and checksum commands
+    pub fn timeout_per_mib(mut self, timeout: Duration) -> Self {
+        self.timeout_per_mib = timeout;
+        self
+    }
+
+    /// Command timeout for processing specified amount of data
+    pub fn data_timeout(&self, size: u64) -> Duration {
+        let mibs = u32::try_from(size.div_ceil(1 << 20)).unwrap_or(u32::MAX);
+        self.timeout_per_mib
+            .checked_mul(mibs)
+            .and_then(|timeout| timeout.checked_add(self.command_timeout))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn data_timeout() {
+        let options = ClientOptions::default()
+            .command_timeout(Duration::from_secs(1))
+            .timeout_per_mib(Duration::from_millis(500));
+        assert_eq!(options.data_timeout(0), Duration::from_secs(1));
+        assert_eq!(options.data_timeout(1), Duration::from_millis(1500));
+        assert_eq!(options.data_timeout(16 << 20), Duration::from_secs(9));
+        assert_eq!(
+            options.data_timeout(u64::MAX),
+            Duration::from_secs(1) + Duration::from_millis(500) * u32::MAX
+        );
+        let options = options.timeout_per_mib(Duration::MAX);
+        assert_eq!(options.data_timeout(1 << 30), Duration::MAX);
+    }
+}
@@ -1,62 +1,334 @@
-use std::{net::IpAddr, path::Path};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
 
+use async_tftp::server::Handler;
 use ipnetwork::IpNetwork;
+use nom::{bytes::complete::tag, sequence::preceded};
+use tokio::{io::AsyncWrite, sync::mpsc};
 
-use crate::{tftp_server::TftpHandler, variables::MemRegion, Map, Result, UBootClient};
+use crate::{
+    parse_utils, tftp_server::TftpHandler, variables::MemRegion, virtual_files::VirtualFiles,
+    Error, Map, Result, TerminalKey, UBootClient,
+};
 
 //const PING_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_millis(150);
 
+/// Standard TFTP server port
+const TFTP_PORT: u16 = 69;
+
+/// Amount of data per progress mark which U-Boot prints (10 blocks of default size)
+const HASH_BYTES: u64 = 10 * 512;
+
+/// Network settings for transfers via built-in TFTP server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TftpOptions {
+    pub(crate) device_ip: IpAddr,
+    pub(crate) server_ip: Option<IpAddr>,
+    pub(crate) port: u16,
+}
+
+impl TftpOptions {
+    /// Device address which should be in same network as host
+    pub fn new(device_ip: IpAddr) -> Self {
+        Self {
+            device_ip,
+            server_ip: None,
+            port: TFTP_PORT,
+        }
+    }
+
+    /// Host address which device connects to (selected by device address by default)
+    pub fn server_ip(mut self, ip: IpAddr) -> Self {
+        self.server_ip = Some(ip);
+        self
+    }
+
+    /// Server port (0 to select free one)
+    ///
+    /// Non-standard port does not require privileges, device is pointed to it by `tftpdstp`.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    fn server_addr(&self) -> Result<SocketAddr> {
+        let ip = match self.server_ip {
+            Some(ip) => ip,
+            None => UBootClient::server_ip(self.device_ip)?,
+        };
+        Ok(SocketAddr::new(ip, self.port))
+    }
+}
+
 impl UBootClient {
     /// Dump MTD part via tftp (fast)
+    ///
+    /// Data is streamed to sink without intermediate files.
     pub async fn dump_mtd_part_tftp(
         &mut self,
+        tftp: &TftpOptions,
         name: impl AsRef<str>,
+        file: impl AsyncWrite + Send + Unpin + 'static,
         region: &MemRegion,
         address: u64,
+        progress: mpsc::Sender<u64>,
+    ) -> Result<()> {
+        let mut session = self.session().await;
+
+        session.read_mtd_part(region, address).await?;
+        session
+            .tftp_send_to(tftp, name, file, address, region.size, progress)
+            .await
+    }
+
+    /// Send memory to host file via TFTP (`tftpput`)
+    ///
+    /// File is received by built-in server and verified using CRC32 of memory.
+    pub async fn tftp_send(
+        &mut self,
+        tftp: &TftpOptions,
+        file: impl AsRef<Path>,
+        address: u64,
+        size: u64,
+        progress: mpsc::Sender<u64>,
+    ) -> Result<()> {
+        let file = file.as_ref();
+        let (_, name) = split_file_path(file)?;
+        let sink = tokio::fs::File::create(file).await?;
+
+        self.tftp_send_to(tftp, name, sink, address, size, progress)
+            .await
+    }
+
+    /// Send memory to sink via TFTP (`tftpput`)
+    ///
+    /// Data is received by built-in server as virtual file with specified name
+    /// and verified using CRC32 of memory.
+    pub async fn tftp_send_to(
+        &mut self,
+        tftp: &TftpOptions,
+        name: impl AsRef<str>,
+        sink: impl AsyncWrite + Send + Unpin + 'static,
+        address: u64,
+        size: u64,
+        progress: mpsc::Sender<u64>,
     ) -> Result<()> {
-        let _name = name.as_ref();
+        let name = name.as_ref();
+        let mut session = self.session().await;
+
+        let files = VirtualFiles::new().auth_ip(tftp.device_ip);
+        let transfer = files.add_sink(name, sink, size);
+        let (addr, server) = spawn_tftp_server(files, tftp.server_addr()?).await?;
+
+        let result = async {
+            session
+                .tftp_run(
+                    tftp,
+                    addr,
+                    format!("tftpput {:#08x} {:#08x} {}", address, size, name),
+                    size,
+                    &progress,
+                )
+                .await?;
+            // sink may be still flushed
+            transfer.finished().await
+        }
+        .await;
 
-        self.read_mtd_part(region, address).await?;
+        server.abort();
+        let received = result?;
 
-        //self.tftp_send(name, address, region.size).await?;
+        let expected = session.calc_crc32(address, size).await?;
+        if expected != received.crc32 {
+            return Err(Error::ChecksumMismatch {
+                expected,
+                actual: received.crc32,
+            });
+        }
 
         Ok(())
     }
 
-    /// Send memory via TFTP
-    pub async fn tftp_send(_name: impl AsRef<str>, _base: u64, _size: u64) -> Result<()> {
-        // TODO:
-        unimplemented! {}
+    /// Load host file to memory via TFTP (`tftpboot`)
+    ///
+    /// File is served by built-in server and loaded data is verified using CRC32.
+    /// Returns size of loaded data.
+    pub async fn tftp_load(
+        &mut self,
+        tftp: &TftpOptions,
+        file: impl AsRef<Path>,
+        address: u64,
+        progress: mpsc::Sender<u64>,
+    ) -> Result<u64> {
+        let file = file.as_ref();
+        let (dir, name) = split_file_path(file)?;
+        let mut session = self.session().await;
+
+        let data = tokio::fs::read(file).await?;
+        let size = data.len() as u64;
+        let expected = crc32fast::hash(&data);
+        drop(data);
+
+        let handler = TftpHandler::new(dir)
+            .auth_ip(tftp.device_ip)
+            .allow_read(true)
+            .allow_file(name);
+        let (addr, server) = spawn_tftp_server(handler, tftp.server_addr()?).await?;
+
+        let result = session
+            .tftp_run(
+                tftp,
+                addr,
+                format!("tftpboot {:#08x} {}", address, name),
+                size,
+                &progress,
+            )
+            .await;
+
+        server.abort();
+        result?;
+
+        let actual = session.calc_crc32(address, size).await?;
+        if expected != actual {
+            return Err(Error::ChecksumMismatch { expected, actual });
+        }
+
+        Ok(size)
+    }
+
+    /// Run TFTP command with server which listens specified address
+    async fn tftp_run(
+        &mut self,
+        tftp: &TftpOptions,
+        server: SocketAddr,
+        cmd: String,
+        size: u64,
+        progress: &mpsc::Sender<u64>,
+    ) -> Result<()> {
+        self.run(format!(
+            "setenv ipaddr {}; setenv serverip {}",
+            tftp.device_ip,
+            server.ip()
+        ))
+        .await?
+        .check()?;
+
+        let dstp = self.set_tftp_port(server.port()).await?;
+        let result = self.tftp_transfer(cmd, size, progress).await;
+        let restored = match dstp {
+            Some(dstp) => self.restore_tftp_port(dstp).await,
+            None => Ok(()),
+        };
+
+        // transfer error is more important
+        let transferred = result?;
+        restored?;
+        if transferred != size {
+            return Err(Error::protocol(format!(
+                "Transferred {} bytes instead of {}",
+                transferred, size
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Point device to server port
+    ///
+    /// Stale `tftpdstp` is changed or cleared whenever it differs from requested port.
+    /// Returns previous value of `tftpdstp` when it is changed.
+    ///
+    /// Source port (`tftpsrcp`) is not touched because server replies to any client port.
+    async fn set_tftp_port(&mut self, port: u16) -> Result<Option<Option<String>>> {
+        let dstp = self.get_environ().await?.get("tftpdstp").cloned();
+        if tftp_port_matches(dstp.as_deref(), port) {
+            return Ok(None);
+        }
+
+        self.run(if port == TFTP_PORT {
+            "setenv tftpdstp".into()
+        } else {
+            format!("setenv tftpdstp {}", port)
+        })
+        .await?
+        .check()?;
+
+        Ok(Some(dstp))
+    }
+
+    /// Restore previous value of `tftpdstp`
+    async fn restore_tftp_port(&mut self, dstp: Option<String>) -> Result<()> {
+        self.run(match dstp {
+            Some(port) => format!("setenv tftpdstp {}", port),
+            None => "setenv tftpdstp".into(),
+        })
+        .await?
+        .check()?;
+        Ok(())
+    }
+
+    /// Run TFTP command and get number of transferred bytes
+    async fn tftp_transfer(
+        &mut self,
+        cmd: String,
+        size: u64,
+        progress: &mpsc::Sender<u64>,
+    ) -> Result<u64> {
+        let mut exec = self
+            .execute_timeout(cmd, self.options().data_timeout(size))
+            .await?;
+
+        let mut hashes = 0;
+        let mut transferred = None;
+
+        while let Some(line) = exec.next_line().await? {
+            if let Some(bytes) = parse_transferred(&line) {
+                transferred = Some(bytes);
+            } else if line.starts_with("TFTP error") || line.contains("Retry count exceeded") {
+                // U-Boot retries endlessly
+                self.send_raw(TerminalKey::Ctrl(b'C').encode().unwrap())
+                    .await?;
+                return Err(Error::protocol(line.trim()));
+            } else {
+                hashes += line.matches('#').count() as u64;
+                // progress is optional for caller
+                let _ = progress.send((hashes * HASH_BYTES).min(size)).await;
+            }
+        }
+
+        let transferred = transferred.ok_or_else(|| Error::not_found("Transferred bytes"))?;
+        let _ = progress.send(transferred).await;
+
+        Ok(transferred)
     }
 
     /// Start TFTP server
+    ///
+    /// Returns address which server listens.
     pub async fn tftp_server(
-        client_ip: IpAddr,
+        tftp: &TftpOptions,
         path: impl AsRef<Path>,
         read: bool,
         write: bool,
-    ) -> Result<tokio::task::JoinHandle<Result<()>>> {
-        let server_ip = Self::server_ip(client_ip)?;
-
+    ) -> Result<(SocketAddr, tokio::task::JoinHandle<Result<()>>)> {
         let handler = TftpHandler::new(path)
-            .auth_ip(client_ip)
+            .auth_ip(tftp.device_ip)
             .allow_read(read)
             .allow_write(write);
 
-        // Build server
-        let tftpd = async_tftp::server::TftpServerBuilder::with_handler(handler)
-            .bind(std::net::SocketAddr::new(server_ip, 69))
-            // Workaround to handle cases where client is behind VPN
-            .block_size_limit(1024)
-            .build()
-            .await?;
-
-        Ok(tokio::task::spawn(async move {
-            // Serve
-            let _ = tftpd.serve().await?;
+        Self::tftp_server_with(tftp, handler).await
+    }
 
-            Ok(())
-        }))
+    /// Start TFTP server with specific handler (e.g. [`VirtualFiles`])
+    ///
+    /// Returns address which server listens.
+    pub async fn tftp_server_with(
+        tftp: &TftpOptions,
+        handler: impl Handler + 'static,
+    ) -> Result<(SocketAddr, tokio::task::JoinHandle<Result<()>>)> {
+        spawn_tftp_server(handler, tftp.server_addr()?).await
     }
 
     /// Get list of networks to configure tftp server
@@ -69,10 +341,14 @@ impl UBootClient {
             let networks = interfaces.entry(iface.name).or_default();
             networks.push(match iface.addr {
                 if_addrs::IfAddr::V4(addr) => {
-                    ipnetwork::Ipv4Network::with_netmask(addr.ip, addr.netmask)?.into()
+                    ipnetwork::Ipv4Network::with_netmask(addr.ip, addr.netmask)
+                        .map_err(|_| Error::parse("netmask", addr.netmask.to_string()))?
+                        .into()
                 }
                 if_addrs::IfAddr::V6(addr) => {
-                    ipnetwork::Ipv6Network::with_netmask(addr.ip, addr.netmask)?.into()
+                    ipnetwork::Ipv6Network::with_netmask(addr.ip, addr.netmask)
+                        .map_err(|_| Error::parse("netmask", addr.netmask.to_string()))?
+                        .into()
                 }
             });
         }
@@ -88,6 +364,94 @@ impl UBootClient {
                 }
             }
         }
-        Err(anyhow::anyhow!("Unable to determine server IP addess"))
+        Err(Error::not_found("Server IP address"))
+    }
+}
+
+async fn spawn_tftp_server(
+    handler: impl Handler + 'static,
+    addr: SocketAddr,
+) -> Result<(SocketAddr, tokio::task::JoinHandle<Result<()>>)> {
+    // Build server
+    let tftpd = async_tftp::server::TftpServerBuilder::with_handler(handler)
+        .bind(addr)
+        // Workaround to handle cases where client is behind VPN
+        .block_size_limit(1024)
+        .build()
+        .await?;
+    let addr = tftpd.listen_addr()?;
+
+    Ok((
+        addr,
+        tokio::task::spawn(async move {
+            // Serve
+            tftpd.serve().await?;
+
+            Ok(())
+        }),
+    ))
+}
+
+/// Split file path to server directory and file name
+fn split_file_path(path: &Path) -> Result<(&Path, &str)> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::parse("file name", path.display().to_string()))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Ok((dir, name))
+}
+
+/// Check that `tftpdstp` value points to server port
+fn tftp_port_matches(dstp: Option<&str>, port: u16) -> bool {
+    match dstp {
+        Some(dstp) => dstp.trim().parse::<u16>().ok() == Some(port),
+        None => port == TFTP_PORT,
+    }
+}
+
+/// Parse `Bytes transferred = 65536 (10000 hex)`
+fn parse_transferred(line: &str) -> Option<u64> {
+    preceded(tag("Bytes transferred = "), parse_utils::dec_u64)(line.trim())
+        .ok()
+        .map(|(_, bytes)| bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transferred() {
+        assert_eq!(
+            parse_transferred("Bytes transferred = 65536 (10000 hex)"),
+            Some(65536)
+        );
+        assert_eq!(parse_transferred("Saving: #####"), None);
+    }
+
+    #[test]
+    fn port_matches() {
+        assert!(tftp_port_matches(None, TFTP_PORT));
+        assert!(tftp_port_matches(Some("69"), TFTP_PORT));
+        assert!(!tftp_port_matches(Some("6969"), TFTP_PORT));
+        assert!(!tftp_port_matches(None, 6969));
+        assert!(tftp_port_matches(Some("6969"), 6969));
+        assert!(!tftp_port_matches(Some("invalid"), 6969));
+    }
+
+    #[test]
+    fn file_path() {
+        assert_eq!(
+            split_file_path(Path::new("/tmp/dump/boot.bin")).unwrap(),
+            (Path::new("/tmp/dump"), "boot.bin")
+        );
+        assert_eq!(
+            split_file_path(Path::new("boot.bin")).unwrap(),
+            (Path::new("."), "boot.bin")
+        );
     }
 }
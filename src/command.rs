@@ -0,0 +1,178 @@
+use core::pin::Pin;
+
+use futures::StreamExt;
+use tokio::time::Duration;
+
+use crate::{
+    client::{Chunks, Payload, Session},
+    framing::{Frame, FramesStream},
+    Error, Result,
+};
+
+/// Marker which is echoed with return code after command
+pub(crate) const RC_MARKER: &str = "__rc=";
+
+/// Command line which reports return code after command
+pub(crate) fn command_line(command: &str) -> String {
+    if command.is_empty() {
+        format!("echo {}$?", RC_MARKER)
+    } else {
+        format!("{}; echo {}$?", command, RC_MARKER)
+    }
+}
+
+/// Captured output of command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub command: String,
+    /// Output lines without echoed command line
+    pub lines: Vec<String>,
+    /// Return code (`None` when shell does not support `$?`)
+    pub code: Option<i32>,
+}
+
+impl CommandOutput {
+    /// Command finished successfully or return code is unknown
+    pub fn success(&self) -> bool {
+        self.code.map(|code| code == 0).unwrap_or(true)
+    }
+
+    /// Convert unsuccessful result to error
+    pub fn check(self) -> Result<Self> {
+        if self.success() {
+            Ok(self)
+        } else {
+            Err(Error::CommandFailed {
+                cmd: self.command,
+                code: self.code.unwrap_or_default(),
+                output: self.lines,
+            })
+        }
+    }
+}
+
+/// Running command
+///
+/// Output lines are received one by one, so long outputs can be processed on the fly.
+/// Command is finished when prompt reappears or, when prompt is unknown,
+/// when return code is received.
+pub struct Execution {
+    command: String,
+    frames: Pin<Box<FramesStream<Chunks>>>,
+    timeout: Duration,
+    prompt_timeout: Duration,
+    partial: Payload,
+    echoed: bool,
+    code: Option<Option<i32>>,
+    finished: bool,
+    session: Session,
+}
+
+impl Execution {
+    pub(crate) fn new(
+        command: String,
+        frames: FramesStream<Chunks>,
+        timeout: Duration,
+        prompt_timeout: Duration,
+        session: Session,
+    ) -> Self {
+        Self {
+            command,
+            frames: Box::pin(frames),
+            timeout,
+            prompt_timeout,
+            partial: Payload::new(),
+            echoed: false,
+            code: None,
+            finished: false,
+            session,
+        }
+    }
+
+    /// Executed command
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Command is finished
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Return code of finished command
+    pub fn code(&self) -> Option<i32> {
+        self.code.flatten()
+    }
+
+    /// Receive next output line (`None` when command is finished)
+    pub async fn next_line(&mut self) -> Result<Option<String>> {
+        while !self.finished {
+            // prompt must follow return code immediately
+            let timeout = if self.code.is_some() {
+                self.prompt_timeout
+            } else {
+                self.timeout
+            };
+
+            let frame = match tokio::time::timeout(timeout, self.frames.next()).await {
+                Ok(Some(Ok(frame))) => frame,
+                Ok(Some(Err(error))) => return Err(error.into()),
+                Ok(None) => return Err(self.session.closed_error().into()),
+                Err(_) if self.code.is_some() => {
+                    self.finished = true;
+                    break;
+                }
+                Err(_) if !self.session.state().is_connected() => {
+                    return Err(self.session.closed_error().into())
+                }
+                Err(_) => return Err(Error::timeout(format!("Command `{}`", self.command))),
+            };
+
+            let data = match frame {
+                Frame::Prompt(_) => {
+                    if self.echoed {
+                        self.finished = true;
+                    }
+                    continue;
+                }
+                // join lines which was split by receive delays
+                Frame::Partial(data) => {
+                    self.partial.extend(data);
+                    continue;
+                }
+                Frame::Line(data) => data,
+            };
+
+            self.partial.extend(data);
+            let line = String::from_utf8_lossy(&self.partial).into_owned();
+            self.partial.clear();
+
+            if let Some(code) = line.strip_prefix(RC_MARKER) {
+                // `$?` may be not supported by shell
+                self.code = Some(code.trim().parse().ok());
+                if self.frames.prompt().is_none() {
+                    self.finished = true;
+                }
+            } else if line.contains(&format!("echo {}$?", RC_MARKER)) {
+                // skip echoed command line and everything before it
+                self.echoed = true;
+            } else if self.echoed {
+                return Ok(Some(line));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Collect rest of output
+    pub async fn output(mut self) -> Result<CommandOutput> {
+        let mut lines = Vec::new();
+        while let Some(line) = self.next_line().await? {
+            lines.push(line);
+        }
+        Ok(CommandOutput {
+            code: self.code(),
+            command: self.command,
+            lines,
+        })
+    }
+}
@@ -0,0 +1,72 @@
+use core::fmt;
+use std::{io, sync::Arc};
+
+/// Transport failure
+///
+/// Same error is reported to every caller which is affected by it.
+#[derive(Debug, Clone)]
+pub struct TransportError(Arc<io::Error>);
+
+impl TransportError {
+    pub(crate) fn closed() -> Self {
+        io::Error::new(io::ErrorKind::BrokenPipe, "Connection closed").into()
+    }
+
+    pub(crate) fn eof() -> Self {
+        io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed by remote").into()
+    }
+
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+
+    /// Original I/O error
+    pub fn io_error(&self) -> &io::Error {
+        &self.0
+    }
+}
+
+impl From<io::Error> for TransportError {
+    fn from(error: io::Error) -> Self {
+        Self(Arc::new(error))
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Transport error: {}", self.0)
+    }
+}
+
+impl std::error::Error for TransportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// Connection state
+#[derive(Debug, Clone)]
+pub enum ConnectionState {
+    Connected,
+    /// Transport is failed, reopening is in progress
+    Reconnecting {
+        /// Attempt number starting from 1
+        attempt: u32,
+        error: TransportError,
+    },
+    /// Transport is failed or closed
+    Disconnected(TransportError),
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, Self::Connected)
+    }
+
+    pub fn error(&self) -> Option<&TransportError> {
+        match self {
+            Self::Connected => None,
+            Self::Reconnecting { error, .. } | Self::Disconnected(error) => Some(error),
+        }
+    }
+}
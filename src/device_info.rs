@@ -0,0 +1,69 @@
+use crate::{
+    flash_info::FlashInfo, variables::MemRegion, version_info::VersionInfo, Map, Result,
+    UBootClient,
+};
+
+/// Summary of device
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    pub version: VersionInfo,
+    pub flash: FlashInfo,
+    pub ram: MemRegion,
+    pub mtd_parts: Map<String, MemRegion>,
+}
+
+impl UBootClient {
+    /// Get version, flash, RAM and MTD parts info
+    pub async fn get_device_info(&mut self) -> Result<DeviceInfo> {
+        let mut session = self.session().await;
+
+        Ok(DeviceInfo {
+            version: session.get_version().await?,
+            flash: session.get_flash_info().await?,
+            ram: session.get_ram_info().await?,
+            mtd_parts: session.get_mtd_parts().await?,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+    use crate::flash_info::FlashKind;
+
+    #[test]
+    fn serde_json() {
+        let mut mtd_parts = Map::default();
+        mtd_parts.insert(
+            "boot".into(),
+            MemRegion {
+                base: 0,
+                size: 0x10000,
+            },
+        );
+        let info = DeviceInfo {
+            version: VersionInfo {
+                year: 2016,
+                month: 11,
+                revision: "g2fc5f58".into(),
+                suffix: "dirty".into(),
+            },
+            flash: FlashInfo::from_kind(FlashKind::Nand),
+            ram: MemRegion {
+                base: 0x4000_0000,
+                size: 0x0400_0000,
+            },
+            mtd_parts,
+        };
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["version"]["year"], 2016);
+        assert_eq!(json["flash"]["kind"], "NAND");
+        assert_eq!(json["mtd_parts"]["boot"]["size"], 0x10000);
+
+        let info: DeviceInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(info.flash.kind, FlashKind::Nand);
+        assert_eq!(info.mtd_parts["boot"].size, 0x10000);
+    }
+}
@@ -0,0 +1,177 @@
+use core::fmt;
+use std::io;
+
+use tokio_serial as serial;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+
+use crate::connection::TransportError;
+
+/// Library error
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Operation is not completed in time
+    Timeout { op: String },
+    /// Output is ended unexpectedly
+    Eof,
+    /// Unable to parse device output or argument
+    Parse { what: &'static str, input: String },
+    /// Expected data is missing in device output
+    NotFound { what: String },
+    /// Transferred data is damaged
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Command returned non-zero code
+    CommandFailed {
+        cmd: String,
+        code: i32,
+        output: Vec<String>,
+    },
+    /// Received data is lost because subscriber is too slow
+    Lagged { skipped: u64 },
+    /// Device responded in unexpected way
+    Protocol(String),
+    /// Connection is failed or closed
+    Transport(TransportError),
+    /// Local I/O error
+    Io(io::Error),
+    /// Local serial port error
+    Serial(serial::Error),
+    /// TFTP server error
+    #[cfg(feature = "tftp")]
+    Tftp(async_tftp::Error),
+}
+
+impl Error {
+    pub(crate) fn timeout(op: impl Into<String>) -> Self {
+        Self::Timeout { op: op.into() }
+    }
+
+    pub(crate) fn parse(what: &'static str, input: impl Into<String>) -> Self {
+        Self::Parse {
+            what,
+            input: input.into(),
+        }
+    }
+
+    pub(crate) fn not_found(what: impl Into<String>) -> Self {
+        Self::NotFound { what: what.into() }
+    }
+
+    pub(crate) fn protocol(msg: impl Into<String>) -> Self {
+        Self::Protocol(msg.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout { op } => write!(f, "{} timeout", op),
+            Self::Eof => "Unexpected end of output".fmt(f),
+            Self::Parse { what, input } => write!(f, "Unable to parse {}: {:?}", what, input),
+            Self::NotFound { what } => write!(f, "{} not found", what),
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Checksum mismatch: expected {:#010x}, actual {:#010x}",
+                expected, actual
+            ),
+            Self::CommandFailed { cmd, code, output } => {
+                write!(f, "Command `{}` failed with code {}", cmd, code)?;
+                if !output.is_empty() {
+                    write!(f, ": {}", output.join("\n"))?;
+                }
+                Ok(())
+            }
+            Self::Lagged { skipped } => write!(f, "Output is lost: {} chunks skipped", skipped),
+            Self::Protocol(msg) => msg.fmt(f),
+            Self::Transport(error) => error.fmt(f),
+            Self::Io(error) => write!(f, "I/O error: {}", error),
+            Self::Serial(error) => write!(f, "Serial port error: {}", error),
+            #[cfg(feature = "tftp")]
+            Self::Tftp(error) => write!(f, "TFTP error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(error) => Some(error),
+            Self::Io(error) => Some(error),
+            Self::Serial(error) => Some(error),
+            #[cfg(feature = "tftp")]
+            Self::Tftp(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransportError> for Error {
+    fn from(error: TransportError) -> Self {
+        Self::Transport(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<serial::Error> for Error {
+    fn from(error: serial::Error) -> Self {
+        Self::Serial(error)
+    }
+}
+
+impl From<BroadcastStreamRecvError> for Error {
+    fn from(error: BroadcastStreamRecvError) -> Self {
+        match error {
+            BroadcastStreamRecvError::Lagged(skipped) => Self::Lagged { skipped },
+        }
+    }
+}
+
+#[cfg(feature = "tftp")]
+impl From<async_tftp::Error> for Error {
+    fn from(error: async_tftp::Error) -> Self {
+        Self::Tftp(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Error::timeout("Command `sf probe`").to_string(),
+            "Command `sf probe` timeout"
+        );
+        assert_eq!(
+            Error::ChecksumMismatch {
+                expected: 0x1234abcd,
+                actual: 0
+            }
+            .to_string(),
+            "Checksum mismatch: expected 0x1234abcd, actual 0x00000000"
+        );
+        assert_eq!(
+            Error::CommandFailed {
+                cmd: "sf probe 0".into(),
+                code: 1,
+                output: vec!["No SPI flash selected.".into()]
+            }
+            .to_string(),
+            "Command `sf probe 0` failed with code 1: No SPI flash selected."
+        );
+    }
+
+    #[test]
+    fn lagged() {
+        assert!(matches!(
+            BroadcastStreamRecvError::Lagged(3).into(),
+            Error::Lagged { skipped: 3 }
+        ));
+    }
+}
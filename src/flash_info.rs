@@ -1,21 +1,18 @@
 use crate::{
     parse_utils::{hex_u8_0x, size_u64},
-    Result,
+    Error, Result,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "UPPERCASE"))]
 #[repr(u8)]
 pub enum FlashKind {
+    #[default]
     Spi,
     Nand,
 }
 
-impl Default for FlashKind {
-    fn default() -> Self {
-        Self::Spi
-    }
-}
-
 impl FlashKind {
     pub fn as_str(&self) -> &'static str {
         match self {
@@ -37,13 +34,13 @@ impl FlashKind {
             ))(input)
         }
 
-        let (_, kind) =
-            parse(src.as_ref()).map_err(|err| anyhow::anyhow!("Invalid sequence: {}", err))?;
+        let (_, kind) = parse(src.as_ref()).map_err(|_| Error::parse("boot mode", src.as_ref()))?;
         Ok(kind)
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FlashInfo {
     /// Chip type
     pub kind: FlashKind,
@@ -124,7 +121,7 @@ impl FlashInfo {
         }
 
         let (_, data) =
-            parse(src.as_ref()).map_err(|err| anyhow::anyhow!("Invalid sequence: {}", err))?;
+            parse(src.as_ref()).map_err(|_| Error::parse("flash info", src.as_ref()))?;
 
         match data {
             Data::Size { block, size, count } => {
@@ -0,0 +1,212 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::collections::VecDeque;
+
+use futures::{Future, Stream};
+
+use crate::client::Payload;
+
+/// Console output frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// Complete line without line ending
+    Line(Payload),
+    /// Shell prompt
+    Prompt(Payload),
+    /// Incomplete line which is followed by silence
+    Partial(Payload),
+}
+
+impl Frame {
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Self::Line(data) | Self::Prompt(data) | Self::Partial(data) => data,
+        }
+    }
+
+    pub fn into_data(self) -> Payload {
+        match self {
+            Self::Line(data) | Self::Prompt(data) | Self::Partial(data) => data,
+        }
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Splits received chunks to frames
+    ///
+    /// Known prompt is recognized immediately when it appears at start of line,
+    /// other incomplete lines are emitted as partial after timeout of silence.
+    pub struct FramesStream<S> {
+        #[pin]
+        stream: S,
+        #[pin]
+        sleep: tokio::time::Sleep,
+        timeout: tokio::time::Duration,
+        prompt: Option<Payload>,
+        buffer: Payload,
+        frames: VecDeque<Frame>,
+    }
+}
+
+impl<S> FramesStream<S> {
+    pub fn new(stream: S, timeout: tokio::time::Duration, prompt: Option<Payload>) -> Self {
+        Self {
+            stream,
+            sleep: tokio::time::sleep(timeout),
+            timeout,
+            prompt,
+            buffer: Payload::new(),
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Prompt which is recognized
+    pub fn prompt(&self) -> Option<&[u8]> {
+        self.prompt.as_deref()
+    }
+}
+
+impl<S, E> Stream for FramesStream<S>
+where
+    S: Stream<Item = Result<Payload, E>>,
+{
+    type Item = Result<Frame, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if let Some(frame) = this.frames.pop_front() {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+
+            match this.stream.as_mut().poll_next(cx) {
+                // data lost, so incomplete line is broken
+                Poll::Ready(Some(Err(error))) => {
+                    this.buffer.clear();
+                    return Poll::Ready(Some(Err(error)));
+                }
+                Poll::Ready(Some(Ok(chunk))) => {
+                    // reset timer
+                    this.sleep
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + *this.timeout);
+
+                    this.buffer.extend(chunk);
+
+                    // split complete lines
+                    while let Some(end) = this.buffer.iter().position(|b| *b == b'\n') {
+                        let mut line: Payload = this.buffer.drain(..=end).collect();
+                        line.pop();
+                        if line.ends_with(b"\r") {
+                            line.pop();
+                        }
+                        this.frames.push_back(Frame::Line(line));
+                    }
+
+                    if this.prompt.as_ref() == Some(this.buffer) {
+                        this.frames
+                            .push_back(Frame::Prompt(core::mem::take(this.buffer)));
+                    }
+                }
+                // end of stream
+                Poll::Ready(None) => {
+                    if this.buffer.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(Ok(Frame::Partial(core::mem::take(this.buffer)))));
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        // timeout reached
+        if !this.buffer.is_empty() && this.sleep.poll(cx).is_ready() {
+            return Poll::Ready(Some(Ok(Frame::Partial(core::mem::take(this.buffer)))));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+    use tokio::time::Duration;
+
+    type Chunk = Result<Payload, &'static str>;
+
+    fn frames(chunks: &[Chunk], prompt: Option<&[u8]>) -> FramesStream<impl Stream<Item = Chunk>> {
+        FramesStream::new(
+            futures::stream::iter(chunks.to_vec()),
+            Duration::from_millis(50),
+            prompt.map(|prompt| prompt.to_vec()),
+        )
+    }
+
+    fn ok(data: &[u8]) -> Chunk {
+        Ok(data.to_vec())
+    }
+
+    #[tokio::test]
+    async fn known_prompt() {
+        let stream = frames(
+            &[ok(b"hel"), ok(b"lo\r\nhisi"), ok(b"licon # ")],
+            Some(b"hisilicon # "),
+        );
+        assert_eq!(
+            stream.collect::<Vec<_>>().await,
+            [
+                Ok(Frame::Line(b"hello".to_vec())),
+                Ok(Frame::Prompt(b"hisilicon # ".to_vec()))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_prompt() {
+        let stream = frames(&[ok(b"a\r\n\r\nb\n"), ok(b"=> ")], None);
+        assert_eq!(
+            stream.collect::<Vec<_>>().await,
+            [
+                Ok(Frame::Line(b"a".to_vec())),
+                Ok(Frame::Line(b"".to_vec())),
+                Ok(Frame::Line(b"b".to_vec())),
+                Ok(Frame::Partial(b"=> ".to_vec()))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lagged() {
+        let stream = frames(&[ok(b"lo"), Err("lagged"), ok(b"st\r\nok\r\n")], None);
+        assert_eq!(
+            stream.collect::<Vec<_>>().await,
+            [
+                Err("lagged"),
+                Ok(Frame::Line(b"st".to_vec())),
+                Ok(Frame::Line(b"ok".to_vec()))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_after_timeout() {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let stream = FramesStream::new(
+            tokio_stream::wrappers::ReceiverStream::new(rx),
+            Duration::from_millis(20),
+            None,
+        );
+        futures::pin_mut!(stream);
+
+        tx.send(ok(b"Hit any key")).await.unwrap();
+        assert_eq!(
+            stream.next().await,
+            Some(Ok(Frame::Partial(b"Hit any key".to_vec())))
+        );
+    }
+}
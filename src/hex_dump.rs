@@ -1,4 +1,4 @@
-use crate::Result;
+use crate::{Error, Result};
 
 #[derive(Debug, Clone, Default, educe::Educe)]
 #[educe(Deref, DerefMut)]
@@ -30,8 +30,8 @@ impl HexDump {
             )(input)
         }
 
-        let (_, data) = parse(src.as_ref())
-            .map_err(|err| anyhow::anyhow!("Unable to parse hexdump line: {}", err))?;
+        let (_, data) =
+            parse(src.as_ref()).map_err(|_| Error::parse("hexdump line", src.as_ref()))?;
 
         Ok(Self { data })
     }
@@ -76,7 +76,7 @@ mod test {
     #[test]
     fn parse_empty() {
         let p = HexDump::parse_line("42000000:\r").unwrap();
-        assert_eq!(&*p, &[]);
+        assert_eq!(&*p, &[] as &[u8]);
     }
 
     #[test]
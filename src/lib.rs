@@ -1,18 +1,66 @@
+mod autoboot;
+mod baud;
 mod client;
+mod client_options;
+mod command;
+mod connection;
+mod device_info;
+mod error;
 mod flash_info;
+mod framing;
 mod hex_dump;
 mod parse_utils;
+mod probe;
+mod reconnect;
+mod recorder;
+mod rfc2217;
+mod serial_config;
+mod telnet;
 mod terminal_key;
+mod transport;
 mod variables;
 mod version_info;
 
+#[cfg(any(test, feature = "simulator"))]
+mod simulator;
+
 #[cfg(feature = "tftp")]
 mod client_tftp;
 #[cfg(feature = "tftp")]
 mod tftp_server;
+#[cfg(feature = "tftp")]
+mod virtual_files;
 
 pub type Map<K, V> = indexmap::IndexMap<K, V, fxhash::FxBuildHasher>;
 
-pub type Result<T> = anyhow::Result<T>;
+pub type Result<T, E = Error> = core::result::Result<T, E>;
 
-pub use client::UBootClient;
+pub use autoboot::AutobootCatch;
+pub use baud::BAUD_RATES;
+pub use client::{Chunks, ConnectionEvents, Session, UBootClient};
+pub use client_options::ClientOptions;
+#[cfg(feature = "tftp")]
+pub use client_tftp::TftpOptions;
+pub use command::{CommandOutput, Execution};
+pub use connection::{ConnectionState, TransportError};
+pub use device_info::DeviceInfo;
+pub use error::Error;
+pub use flash_info::{FlashInfo, FlashKind};
+pub use framing::{Frame, FramesStream};
+pub use probe::{Console, PortInfo, PortKind, PortProbe};
+pub use reconnect::ReconnectPolicy;
+pub use recorder::{Direction, Record, Recorder, ReplayStream};
+pub use rfc2217::{ComPortState, Rfc2217Stream};
+pub use serial_config::SerialConfig;
+#[cfg(any(test, feature = "simulator"))]
+pub use simulator::Simulator;
+pub use telnet::TelnetStream;
+pub use terminal_key::{AutobootStop, TerminalKey};
+#[cfg(feature = "tftp")]
+pub use tftp_server::TftpHandler;
+pub use tokio_serial::{DataBits, FlowControl, Parity, StopBits};
+pub use transport::{Endpoint, Transport};
+pub use variables::{MemRegion, Variables};
+pub use version_info::VersionInfo;
+#[cfg(feature = "tftp")]
+pub use virtual_files::{Transfer, Transferred, VirtualFiles};
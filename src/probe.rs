@@ -0,0 +1,208 @@
+use tokio::time::Duration;
+use tokio_serial as serial;
+
+use crate::{baud::talk, serial_config::SerialConfig, transport::Transport, Result, UBootClient};
+
+/// Time to wait console response on each port
+const PROBE_TIME: Duration = Duration::from_millis(300);
+
+/// Kind of local serial port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum PortKind {
+    Usb,
+    Pci,
+    Bluetooth,
+    Unknown,
+}
+
+impl PortKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Usb => "usb",
+            Self::Pci => "pci",
+            Self::Bluetooth => "bluetooth",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Local serial port description
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PortInfo {
+    pub name: String,
+    pub kind: PortKind,
+    /// USB vendor ID
+    pub vid: Option<u16>,
+    /// USB product ID
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+impl From<serial::SerialPortInfo> for PortInfo {
+    fn from(info: serial::SerialPortInfo) -> Self {
+        let mut port = Self {
+            name: info.port_name,
+            kind: PortKind::Unknown,
+            vid: None,
+            pid: None,
+            serial_number: None,
+            manufacturer: None,
+            product: None,
+        };
+        match info.port_type {
+            serial::SerialPortType::UsbPort(usb) => {
+                port.kind = PortKind::Usb;
+                port.vid = Some(usb.vid);
+                port.pid = Some(usb.pid);
+                port.serial_number = usb.serial_number;
+                port.manufacturer = usb.manufacturer;
+                port.product = usb.product;
+            }
+            serial::SerialPortType::PciPort => port.kind = PortKind::Pci,
+            serial::SerialPortType::BluetoothPort => port.kind = PortKind::Bluetooth,
+            serial::SerialPortType::Unknown => (),
+        }
+        port
+    }
+}
+
+/// Console which answers on port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Console {
+    #[cfg_attr(feature = "serde", serde(rename = "u-boot"))]
+    UBoot,
+    Linux,
+    /// Something answers but it is not recognized (maybe other baud rate)
+    Unknown,
+}
+
+impl Console {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UBoot => "u-boot",
+            Self::Linux => "linux",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Result of port probing
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PortProbe {
+    pub port: PortInfo,
+    /// Console which answers (`None` when port is silent)
+    pub console: Option<Console>,
+    /// Reason why port cannot be probed (busy, no permissions)
+    pub error: Option<String>,
+}
+
+impl UBootClient {
+    /// Get local serial ports with metadata
+    pub fn port_infos() -> Result<Vec<PortInfo>> {
+        let ports = serial::available_ports()?;
+        Ok(ports.into_iter().map(PortInfo::from).collect())
+    }
+
+    /// Find ports which have console attached
+    ///
+    /// Each port is opened briefly to send Ctrl-C and CR. Break is not sent
+    /// because it followed by key triggers magic SysRq on Linux consoles.
+    pub async fn probe_ports(config: &SerialConfig) -> Result<Vec<PortProbe>> {
+        let mut probes = Vec::new();
+        for port in Self::port_infos()? {
+            let result = match config.open(&port.name) {
+                Ok(stream) => probe_console(stream).await,
+                Err(error) => Err(error.into()),
+            };
+            let (console, error) = match result {
+                Ok(console) => (console, None),
+                Err(error) => (None, Some(error.to_string())),
+            };
+            probes.push(PortProbe {
+                port,
+                console,
+                error,
+            });
+        }
+        Ok(probes)
+    }
+}
+
+/// Detect console on transport
+pub(crate) async fn probe_console<T>(mut port: T) -> Result<Option<Console>>
+where
+    T: Transport + Unpin,
+{
+    let data = talk(&mut port, b"\x03\r", PROBE_TIME).await?;
+    Ok(classify(&data))
+}
+
+/// Recognize console by its response
+fn classify(data: &[u8]) -> Option<Console> {
+    const UBOOT: [&[u8]; 4] = [b"<INTERRUPT>", b"U-Boot", b"Unknown command", b"autoboot"];
+    // Linux terminal echoes Ctrl-C as `^C`
+    const LINUX: [&[u8]; 5] = [b"^C", b"login:", b"Password:", b"BusyBox", b"Linux"];
+
+    let contains = |marker: &&[u8]| data.windows(marker.len()).any(|w| w == *marker);
+
+    if data.is_empty() {
+        None
+    } else if UBOOT.iter().any(contains) {
+        Some(Console::UBoot)
+    } else if LINUX.iter().any(contains) {
+        Some(Console::Linux)
+    } else {
+        Some(Console::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Simulator;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn classify_response() {
+        assert_eq!(classify(b""), None);
+        assert_eq!(
+            classify(b"<INTERRUPT>\r\nhisilicon # \r\nhisilicon # "),
+            Some(Console::UBoot)
+        );
+        assert_eq!(classify(b"^C\r\n~ # \r\n~ # "), Some(Console::Linux));
+        assert_eq!(classify(b"\r\nCamera login: "), Some(Console::Linux));
+        assert_eq!(classify(b"\x80\xfe\x00"), Some(Console::Unknown));
+    }
+
+    #[tokio::test]
+    async fn probe_uboot() {
+        let (host, device) = tokio::io::duplex(256);
+        tokio::spawn(Simulator::new().run(device));
+        assert_eq!(probe_console(host).await.unwrap(), Some(Console::UBoot));
+    }
+
+    #[tokio::test]
+    async fn probe_linux() {
+        let (host, mut device) = tokio::io::duplex(256);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            let _ = device.read(&mut buf).await;
+            let _ = device.write_all(b"^C\r\n~ # \r\n~ # ").await;
+        });
+        assert_eq!(probe_console(host).await.unwrap(), Some(Console::Linux));
+    }
+
+    #[tokio::test]
+    async fn probe_silent() {
+        let (host, _device) = tokio::io::duplex(256);
+        assert_eq!(probe_console(host).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::Mutex;
+
+use tokio::time::Duration;
+use tokio_serial as serial;
+
+use crate::{
+    client::{open_transport, BoxTransport},
+    serial_config::SerialConfig,
+    transport::Endpoint,
+    Result,
+};
+
+/// Reconnect policy
+///
+/// Delay between attempts is doubled after each failure up to maximum delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub(crate) initial_delay: Duration,
+    pub(crate) max_delay: Duration,
+    pub(crate) max_attempts: Option<u32>,
+    pub(crate) by_serial_number: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            max_attempts: None,
+            by_serial_number: true,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Give up after number of failed attempts (unlimited by default)
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// Find USB serial adapter by serial number when port name is changed
+    pub fn by_serial_number(mut self, enable: bool) -> Self {
+        self.by_serial_number = enable;
+        self
+    }
+
+    /// Delay before attempt (starting from 1)
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        (self.initial_delay * factor).min(self.max_delay)
+    }
+
+    pub(crate) fn exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts
+            .map(|max_attempts| attempt > max_attempts)
+            .unwrap_or(false)
+    }
+}
+
+/// Opens same endpoint again
+pub(crate) struct Reopen {
+    endpoint: Endpoint,
+    /// Line settings which is changed by baud rate switching
+    config: Mutex<SerialConfig>,
+    serial_number: Option<String>,
+}
+
+impl Reopen {
+    pub fn new(endpoint: &Endpoint, config: &SerialConfig, by_serial_number: bool) -> Self {
+        let serial_number = match endpoint {
+            Endpoint::Serial(name) if by_serial_number => usb_serial_number(name),
+            _ => None,
+        };
+        Self {
+            endpoint: endpoint.clone(),
+            config: Mutex::new(*config),
+            serial_number,
+        }
+    }
+
+    /// Baud rate when endpoint supports its changing
+    pub fn baud_rate(&self) -> Option<u32> {
+        match self.endpoint {
+            Endpoint::Serial(_) | Endpoint::Rfc2217(_) => {
+                Some(self.config.lock().unwrap().baud_rate)
+            }
+            Endpoint::Tcp(_) | Endpoint::Telnet(_) => None,
+        }
+    }
+
+    pub async fn open(&self) -> Result<BoxTransport> {
+        let config = *self.config.lock().unwrap();
+        self.open_with(&config).await
+    }
+
+    /// Open at another baud rate which is used since success
+    pub async fn open_at(&self, rate: u32) -> Result<BoxTransport> {
+        let config = self.config.lock().unwrap().baud_rate(rate);
+        let transport = self.open_with(&config).await?;
+        *self.config.lock().unwrap() = config;
+        Ok(transport)
+    }
+
+    async fn open_with(&self, config: &SerialConfig) -> Result<BoxTransport> {
+        if let Some(name) = self
+            .serial_number
+            .as_ref()
+            .and_then(|serial_number| find_port_by_serial_number(serial_number))
+        {
+            return open_transport(&Endpoint::Serial(name), config).await;
+        }
+        open_transport(&self.endpoint, config).await
+    }
+}
+
+/// USB serial number of local port
+fn usb_serial_number(name: &str) -> Option<String> {
+    serial::available_ports()
+        .ok()?
+        .into_iter()
+        .find(|port| port.port_name == name)
+        .and_then(|port| match port.port_type {
+            serial::SerialPortType::UsbPort(info) => info.serial_number,
+            _ => None,
+        })
+}
+
+/// Find local port by USB serial number
+fn find_port_by_serial_number(serial_number: &str) -> Option<String> {
+    serial::available_ports()
+        .ok()?
+        .into_iter()
+        .find(|port| {
+            matches!(
+                &port.port_type,
+                serial::SerialPortType::UsbPort(info)
+                    if info.serial_number.as_deref() == Some(serial_number)
+            )
+        })
+        .map(|port| port.port_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let policy = ReconnectPolicy::default()
+            .initial_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(500))
+            .max_attempts(5);
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(4), Duration::from_millis(500));
+        assert!(!policy.exhausted(5));
+        assert!(policy.exhausted(6));
+    }
+}
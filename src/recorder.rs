@@ -0,0 +1,374 @@
+use core::{
+    fmt,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use std::{io, path::Path};
+
+use futures::Future;
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf},
+    time::{Duration, Instant, Sleep},
+};
+
+use crate::{Error, Result};
+
+/// Direction of console traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from device
+    Rx,
+    /// Sent to device
+    Tx,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rx => "rx",
+            Self::Tx => "tx",
+        }
+    }
+}
+
+/// Single recorded chunk of console traffic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Time since start of recording
+    pub time: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:06} {} ",
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            self.direction.as_str()
+        )?;
+        for byte in &self.data {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Record {
+    /// Parse record line (`1.000250 rx 48690d0a`)
+    pub fn parse(src: impl AsRef<str>) -> Result<Self> {
+        use crate::parse_utils::{dec_u64, hex_dig};
+        use nom::{
+            branch::alt,
+            bytes::complete::tag,
+            character::complete::{char, digit1, space0, space1},
+            combinator::{all_consuming, map, map_res},
+            multi::many0,
+            sequence::tuple,
+            IResult,
+        };
+
+        // 1.000250 rx 48690d0a
+        fn parse(input: &str) -> IResult<&str, Record> {
+            all_consuming(map(
+                tuple((
+                    dec_u64,
+                    char('.'),
+                    map_res(digit1, |micros: &str| {
+                        format!("{:0<6}", micros)[..6].parse::<u64>()
+                    }),
+                    space1,
+                    alt((
+                        map(tag("rx"), |_| Direction::Rx),
+                        map(tag("tx"), |_| Direction::Tx),
+                    )),
+                    space0,
+                    many0(map(tuple((hex_dig, hex_dig)), |(h, l)| (h << 4) | l)),
+                )),
+                |(secs, _, micros, _, direction, _, data)| Record {
+                    time: Duration::from_secs(secs) + Duration::from_micros(micros),
+                    direction,
+                    data,
+                },
+            ))(input)
+        }
+
+        let (_, record) =
+            parse(src.as_ref().trim_end()).map_err(|_| Error::parse("record", src.as_ref()))?;
+        Ok(record)
+    }
+
+    /// Parse recorded session (empty lines and `#` comments are skipped)
+    pub fn parse_session(src: impl AsRef<str>) -> Result<Vec<Self>> {
+        src.as_ref()
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(Self::parse)
+            .collect()
+    }
+}
+
+/// Console traffic recorder
+///
+/// Writes each chunk as timestamped line of hex data.
+pub struct Recorder {
+    writer: Pin<Box<dyn AsyncWrite + Send + Sync>>,
+    start: Instant,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("start", &self.start)
+            .finish()
+    }
+}
+
+impl Recorder {
+    /// Record to writer
+    pub fn new(writer: impl AsyncWrite + Send + Sync + 'static) -> Self {
+        Self {
+            writer: Box::pin(writer),
+            start: Instant::now(),
+        }
+    }
+
+    /// Record to file
+    pub async fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = tokio::fs::File::create(path).await?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+
+    pub async fn record(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let record = Record {
+            time: self.start.elapsed(),
+            direction,
+            data: data.into(),
+        };
+        self.writer
+            .write_all(format!("{}\n", record).as_bytes())
+            .await?;
+        // keep log complete even when process is killed
+        self.writer.flush().await
+    }
+}
+
+/// Transport which plays recorded session back
+///
+/// Received chunks are fed to client with original timing scaled by speed factor.
+/// Chunks which was received after client sent something are held until client
+/// sends same number of bytes, so replay does not depend on client timing.
+pub struct ReplayStream {
+    records: Vec<Record>,
+    speed: f64,
+    /** Current record */
+    index: usize,
+    /** Read offset in current record */
+    offset: usize,
+    /** Number of sent bytes which is not matched with records yet */
+    tx_credit: usize,
+    /** Time and record time of last event */
+    anchor: (Instant, Duration),
+    sleep: Option<Pin<Box<Sleep>>>,
+    read_waker: Option<Waker>,
+}
+
+impl ReplayStream {
+    pub fn new(mut records: Vec<Record>) -> Self {
+        // empty chunk would be read as end of stream
+        records.retain(|record| !record.data.is_empty());
+        Self {
+            records,
+            speed: 1.0,
+            index: 0,
+            offset: 0,
+            tx_credit: 0,
+            anchor: (Instant::now(), Duration::ZERO),
+            sleep: None,
+            read_waker: None,
+        }
+    }
+
+    /// Load recorded session from file
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let src = tokio::fs::read_to_string(path).await?;
+        Ok(Self::new(Record::parse_session(src)?))
+    }
+
+    /// Set playback speed factor (`f64::INFINITY` to play without delays)
+    ///
+    /// Factor must be positive.
+    pub fn speed(mut self, speed: f64) -> Result<Self> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(Error::parse("replay speed", speed.to_string()));
+        }
+        self.speed = speed;
+        Ok(self)
+    }
+
+    /// Consume sent records which is covered by sent data
+    fn consume_tx(&mut self) {
+        while let Some(record) = self.records.get(self.index) {
+            if record.direction != Direction::Tx {
+                break;
+            }
+            let remain = record.data.len() - self.offset;
+            if self.tx_credit < remain {
+                self.offset += self.tx_credit;
+                self.tx_credit = 0;
+                break;
+            }
+            self.tx_credit -= remain;
+            self.index += 1;
+            self.offset = 0;
+            self.anchor = (Instant::now(), record.time);
+        }
+    }
+}
+
+impl AsyncRead for ReplayStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        this.consume_tx();
+
+        let record = match this.records.get(this.index) {
+            Some(record) => record,
+            // end of session
+            None => return Poll::Ready(Ok(())),
+        };
+
+        if record.direction == Direction::Tx {
+            // wait for client
+            this.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        if this.offset == 0 {
+            let sleep = this.sleep.get_or_insert_with(|| {
+                let (instant, time) = this.anchor;
+                let delay = record.time.saturating_sub(time).div_f64(this.speed);
+                Box::pin(tokio::time::sleep_until(instant + delay))
+            });
+            futures::ready!(sleep.as_mut().poll(cx));
+            this.sleep = None;
+        }
+
+        let data = &record.data[this.offset..];
+        let len = data.len().min(buf.remaining());
+        buf.put_slice(&data[..len]);
+        this.offset += len;
+
+        if this.offset == record.data.len() {
+            this.anchor = (Instant::now(), record.time);
+            this.index += 1;
+            this.offset = 0;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ReplayStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        this.tx_credit += data.len();
+        if let Some(waker) = this.read_waker.take() {
+            waker.wake();
+        }
+
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Simulator, UBootClient};
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn record_format() {
+        let record = Record {
+            time: Duration::from_micros(1_000_250),
+            direction: Direction::Rx,
+            data: b"Hi\r\n".to_vec(),
+        };
+        assert_eq!(record.to_string(), "1.000250 rx 48690d0a");
+        assert_eq!(Record::parse("1.000250 rx 48690d0a\n").unwrap(), record);
+    }
+
+    #[test]
+    fn record_damaged() {
+        for line in ["1.0 rx 486", "0.1 rx 48zz", "0.1 xx 48"] {
+            assert!(matches!(Record::parse(line), Err(Error::Parse { .. })));
+        }
+    }
+
+    #[test]
+    fn parse_session() {
+        let r = Record::parse_session("# session\n0.5 tx 0d\n\n0.000010 rx \n").unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].time, Duration::from_millis(500));
+        assert_eq!(r[0].direction, Direction::Tx);
+        assert_eq!(r[0].data, b"\r");
+        assert_eq!(r[1].data, b"");
+    }
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let (log_writer, mut log_reader) = tokio::io::duplex(64 << 10);
+
+        let mut client = UBootClient::from_transport(Simulator::new().spawn());
+        client.record(Recorder::new(log_writer)).await.unwrap();
+        let version = client.get_version().await.unwrap();
+        drop(client);
+
+        let mut log = String::new();
+        log_reader.read_to_string(&mut log).await.unwrap();
+        let records = Record::parse_session(&log).unwrap();
+        assert_eq!(records[0].direction, Direction::Tx);
+        assert!(records[0].data.starts_with(b"getinfo version"));
+
+        let replay = ReplayStream::new(records).speed(10.0).unwrap();
+        let mut client = UBootClient::from_transport(replay);
+        assert_eq!(client.get_version().await.unwrap(), version);
+    }
+
+    #[test]
+    fn replay_speed() {
+        for speed in [0.0, -1.0, f64::NAN] {
+            assert!(ReplayStream::new(Vec::new()).speed(speed).is_err());
+        }
+        assert!(ReplayStream::new(Vec::new()).speed(f64::INFINITY).is_ok());
+    }
+
+    #[tokio::test]
+    async fn replay_empty_chunk() {
+        let records = Record::parse_session("0.1 rx \n0.2 rx 4869\n").unwrap();
+        let mut replay = ReplayStream::new(records).speed(f64::INFINITY).unwrap();
+        let mut data = Vec::new();
+        replay.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"Hi");
+    }
+}
@@ -0,0 +1,356 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::io;
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpStream, ToSocketAddrs},
+};
+use tokio_serial as serial;
+
+use crate::{
+    serial_config::SerialConfig,
+    telnet::{connect_tcp, opt, Codec, TelnetStream},
+};
+
+/// COM-PORT-OPTION
+const COM_PORT: u8 = 44;
+
+/// COM-PORT-OPTION client commands (server responses are offset by 100)
+mod com {
+    pub const SET_BAUDRATE: u8 = 1;
+    pub const SET_DATASIZE: u8 = 2;
+    pub const SET_PARITY: u8 = 3;
+    pub const SET_STOPSIZE: u8 = 4;
+    pub const SET_CONTROL: u8 = 5;
+    pub const PURGE_DATA: u8 = 12;
+
+    pub const SERVER: u8 = 100;
+}
+
+/// Remote port settings reported by server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ComPortState {
+    pub baud_rate: Option<u32>,
+    pub data_bits: Option<serial::DataBits>,
+    pub parity: Option<serial::Parity>,
+    pub stop_bits: Option<serial::StopBits>,
+    pub flow_control: Option<serial::FlowControl>,
+}
+
+impl ComPortState {
+    fn update(&mut self, sub: &[u8]) {
+        let (code, data) = match sub {
+            [COM_PORT, code, data @ ..] if *code > com::SERVER => (code - com::SERVER, data),
+            _ => return,
+        };
+        match (code, data) {
+            (com::SET_BAUDRATE, &[a, b, c, d]) => {
+                self.baud_rate = Some(u32::from_be_bytes([a, b, c, d]));
+            }
+            (com::SET_DATASIZE, &[size]) => {
+                self.data_bits = match size {
+                    5 => Some(serial::DataBits::Five),
+                    6 => Some(serial::DataBits::Six),
+                    7 => Some(serial::DataBits::Seven),
+                    8 => Some(serial::DataBits::Eight),
+                    _ => None,
+                };
+            }
+            (com::SET_PARITY, &[parity]) => {
+                self.parity = match parity {
+                    1 => Some(serial::Parity::None),
+                    2 => Some(serial::Parity::Odd),
+                    3 => Some(serial::Parity::Even),
+                    _ => None,
+                };
+            }
+            (com::SET_STOPSIZE, &[size]) => {
+                self.stop_bits = match size {
+                    1 => Some(serial::StopBits::One),
+                    2 => Some(serial::StopBits::Two),
+                    _ => None,
+                };
+            }
+            (com::SET_CONTROL, &[control]) => {
+                // values above 3 belongs to BREAK/DTR/RTS and inbound flow control
+                match control {
+                    1 => self.flow_control = Some(serial::FlowControl::None),
+                    2 => self.flow_control = Some(serial::FlowControl::Software),
+                    3 => self.flow_control = Some(serial::FlowControl::Hardware),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// RFC 2217 (Telnet COM port control) client stream
+///
+/// Line settings are sent to server with `set_*` methods,
+/// the values which server actually applied are available via [`Rfc2217Stream::state`].
+pub struct Rfc2217Stream {
+    inner: TelnetStream<TcpStream>,
+    state: ComPortState,
+}
+
+impl Rfc2217Stream {
+    /// Connect to RFC 2217 server and apply line settings
+    pub async fn connect(addr: impl ToSocketAddrs, config: &SerialConfig) -> io::Result<Self> {
+        let codec = Codec::new(
+            &[opt::BINARY, opt::SGA, COM_PORT],
+            &[opt::BINARY, opt::SGA, opt::ECHO],
+        );
+        let inner = TelnetStream::with_codec(
+            connect_tcp(addr).await?,
+            codec,
+            &[opt::BINARY, opt::SGA, COM_PORT],
+            &[opt::BINARY, opt::SGA],
+        );
+        let mut stream = Self {
+            inner,
+            state: ComPortState::default(),
+        };
+        stream.configure(config).await?;
+        Ok(stream)
+    }
+
+    /// Apply line settings
+    ///
+    /// Exclusive access flag is not applicable to remote ports.
+    pub async fn configure(&mut self, config: &SerialConfig) -> io::Result<()> {
+        self.set_baud_rate(config.baud_rate).await?;
+        self.set_data_bits(config.data_bits).await?;
+        self.set_parity(config.parity).await?;
+        self.set_stop_bits(config.stop_bits).await?;
+        self.set_flow_control(config.flow_control).await?;
+        if let Some(dtr) = config.dtr {
+            self.set_dtr(dtr).await?;
+        }
+        if let Some(rts) = config.rts {
+            self.set_rts(rts).await?;
+        }
+        Ok(())
+    }
+
+    /// Remote port settings confirmed by server
+    pub fn state(&self) -> &ComPortState {
+        &self.state
+    }
+
+    /// Send COM-PORT-OPTION command
+    ///
+    /// Commands wait until server accepts option and ignored when it refuses.
+    async fn command(&mut self, code: u8, data: &[u8]) -> io::Result<()> {
+        let mut payload = vec![code];
+        payload.extend(data);
+        self.inner
+            .codec
+            .send_sub(COM_PORT, &payload, &mut self.inner.out_buf);
+        self.flush().await
+    }
+
+    /// Set baud rate
+    pub async fn set_baud_rate(&mut self, rate: u32) -> io::Result<()> {
+        self.command(com::SET_BAUDRATE, &rate.to_be_bytes()).await
+    }
+
+    /// Set number of data bits
+    pub async fn set_data_bits(&mut self, data_bits: serial::DataBits) -> io::Result<()> {
+        let size = match data_bits {
+            serial::DataBits::Five => 5,
+            serial::DataBits::Six => 6,
+            serial::DataBits::Seven => 7,
+            serial::DataBits::Eight => 8,
+        };
+        self.command(com::SET_DATASIZE, &[size]).await
+    }
+
+    /// Set parity
+    pub async fn set_parity(&mut self, parity: serial::Parity) -> io::Result<()> {
+        let parity = match parity {
+            serial::Parity::None => 1,
+            serial::Parity::Odd => 2,
+            serial::Parity::Even => 3,
+        };
+        self.command(com::SET_PARITY, &[parity]).await
+    }
+
+    /// Set number of stop bits
+    pub async fn set_stop_bits(&mut self, stop_bits: serial::StopBits) -> io::Result<()> {
+        let size = match stop_bits {
+            serial::StopBits::One => 1,
+            serial::StopBits::Two => 2,
+        };
+        self.command(com::SET_STOPSIZE, &[size]).await
+    }
+
+    /// Set flow control
+    pub async fn set_flow_control(&mut self, flow_control: serial::FlowControl) -> io::Result<()> {
+        let control = match flow_control {
+            serial::FlowControl::None => 1,
+            serial::FlowControl::Software => 2,
+            serial::FlowControl::Hardware => 3,
+        };
+        self.command(com::SET_CONTROL, &[control]).await
+    }
+
+    /// Set DTR signal state
+    pub async fn set_dtr(&mut self, on: bool) -> io::Result<()> {
+        self.command(com::SET_CONTROL, &[if on { 8 } else { 9 }])
+            .await
+    }
+
+    /// Set RTS signal state
+    pub async fn set_rts(&mut self, on: bool) -> io::Result<()> {
+        self.command(com::SET_CONTROL, &[if on { 11 } else { 12 }])
+            .await
+    }
+
+    /// Drop data buffered by remote port in both directions
+    pub async fn purge(&mut self) -> io::Result<()> {
+        self.command(com::PURGE_DATA, &[3]).await
+    }
+
+    fn update_state(&mut self) {
+        for sub in self.inner.codec.subs.drain(..) {
+            self.state.update(&sub);
+        }
+    }
+}
+
+impl AsyncRead for Rfc2217Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.update_state();
+        poll
+    }
+}
+
+impl AsyncWrite for Rfc2217Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, data)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::telnet::{cmd, encode_sub};
+    use tokio::io::AsyncReadExt;
+
+    fn sub(payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_sub(COM_PORT, payload, &mut out);
+        out
+    }
+
+    #[tokio::test]
+    async fn set_line_settings() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            let mut buf = [0u8; 15];
+            socket.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf[6..9], &[cmd::IAC, cmd::WILL, COM_PORT]);
+
+            // commands are sent only when option is accepted
+            socket
+                .write_all(&[cmd::IAC, cmd::DO, COM_PORT])
+                .await
+                .unwrap();
+
+            let expected = [
+                sub(&[1, 0x00, 0x01, 0xc2, 0x00]),
+                sub(&[2, 8]),
+                sub(&[3, 3]),
+                sub(&[4, 1]),
+                sub(&[5, 3]),
+                sub(&[5, 12]),
+            ]
+            .concat();
+            let mut buf = vec![0u8; expected.len()];
+            socket.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, expected);
+
+            let mut reply = sub(&[101, 0x00, 0x01, 0xc2, 0x00]);
+            reply.extend(sub(&[103, 3]));
+            reply.push(b'>');
+            socket.write_all(&reply).await.unwrap();
+        });
+
+        let config = SerialConfig::new(115200)
+            .parity(serial::Parity::Even)
+            .flow_control(serial::FlowControl::Hardware)
+            .rts(false);
+        let mut stream = Rfc2217Stream::connect(addr, &config).await.unwrap();
+
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b">");
+        assert_eq!(stream.state().baud_rate, Some(115200));
+        assert_eq!(stream.state().parity, Some(serial::Parity::Even));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refused_option() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            let mut buf = [0u8; 15];
+            socket.read_exact(&mut buf).await.unwrap();
+
+            socket
+                .write_all(&[cmd::IAC, cmd::DONT, COM_PORT, b'>'])
+                .await
+                .unwrap();
+
+            // no commands and no reply to refusal
+            let mut buf = [0u8; 1];
+            socket.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"x");
+        });
+
+        let mut stream = Rfc2217Stream::connect(addr, &SerialConfig::new(115200))
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b">");
+        stream.purge().await.unwrap();
+        stream.write_all(b"x").await.unwrap();
+        stream.flush().await.unwrap();
+        assert_eq!(stream.state(), &ComPortState::default());
+
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+use tokio_serial as serial;
+
+/// Serial line settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub(crate) baud_rate: u32,
+    pub(crate) data_bits: serial::DataBits,
+    pub(crate) parity: serial::Parity,
+    pub(crate) stop_bits: serial::StopBits,
+    pub(crate) flow_control: serial::FlowControl,
+    pub(crate) exclusive: bool,
+    pub(crate) dtr: Option<bool>,
+    pub(crate) rts: Option<bool>,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self::new(115200)
+    }
+}
+
+impl SerialConfig {
+    /// 8N1 without flow control and with exclusive access
+    pub fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            data_bits: serial::DataBits::Eight,
+            parity: serial::Parity::None,
+            stop_bits: serial::StopBits::One,
+            flow_control: serial::FlowControl::None,
+            exclusive: true,
+            dtr: None,
+            rts: None,
+        }
+    }
+
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn data_bits(mut self, data_bits: serial::DataBits) -> Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    pub fn parity(mut self, parity: serial::Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: serial::StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    pub fn flow_control(mut self, flow_control: serial::FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    /// Lock port to prevent opening it by other processes (local ports only)
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Initial DTR state (unchanged by default)
+    pub fn dtr(mut self, dtr: bool) -> Self {
+        self.dtr = Some(dtr);
+        self
+    }
+
+    /// Initial RTS state (unchanged by default)
+    pub fn rts(mut self, rts: bool) -> Self {
+        self.rts = Some(rts);
+        self
+    }
+
+    /// Open local serial port
+    pub(crate) fn open(&self, name: &str) -> serial::Result<serial::SerialStream> {
+        use serial::SerialPort;
+
+        let builder = serial::new(name, self.baud_rate)
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .flow_control(self.flow_control);
+
+        let mut port = serial::SerialStream::open(&builder)?;
+
+        #[cfg(unix)]
+        port.set_exclusive(self.exclusive)?;
+
+        if let Some(dtr) = self.dtr {
+            port.write_data_terminal_ready(dtr)?;
+        }
+        if let Some(rts) = self.rts {
+            port.write_request_to_send(rts)?;
+        }
+
+        Ok(port)
+    }
+}
@@ -0,0 +1,837 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    net::UdpSocket,
+    time::Duration,
+};
+
+use crate::{terminal_key::TerminalKey, Map};
+
+/// Server port unless `tftpdstp` is set
+const TFTP_PORT: u16 = 69;
+const TFTP_BLOCK_SIZE: usize = 512;
+const TFTP_TIMEOUT: Duration = Duration::from_millis(500);
+const TFTP_RETRIES: usize = 5;
+
+const TFTP_RRQ: u8 = 1;
+const TFTP_WRQ: u8 = 2;
+const TFTP_DATA: u8 = 3;
+const TFTP_ACK: u8 = 4;
+const TFTP_ERROR: u8 = 5;
+
+/// Simulated U-Boot device
+///
+/// Speaks U-Boot console protocol over in-memory stream,
+/// so client can be tested without real hardware.
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    prompt: String,
+    version: String,
+    power_on: Option<Duration>,
+    autoboot: Option<(u32, TerminalKey)>,
+    autoboot_prompt: Option<String>,
+    tick: Duration,
+    environ: Map<String, String>,
+    ram_base: u64,
+    ram_size: u64,
+    flash_nand: bool,
+    flash_name: String,
+    flash_id: [u8; 3],
+    flash_block: u64,
+    flash: Vec<u8>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    /// HiSilicon camera which already sits at prompt with 256KiB SPI flash
+    pub fn new() -> Self {
+        let mut environ = Map::default();
+        for (key, value) in [
+            ("bootargs", "mem=39M console=ttyAMA0,115200 root=/dev/mtdblock2 rootfstype=squashfs mtdparts=hi_sfc:0x10000(boot),0x20000(kernel),0x10000(rootfs)"),
+            ("bootcmd", "sf probe 0;sf read 0x42000000 0x10000 0x20000;bootm 0x42000000"),
+            ("bootdelay", "1"),
+            ("baudrate", "115200"),
+            ("ethaddr", "00:12:34:56:78:9a"),
+            ("ipaddr", "192.168.1.10"),
+            ("serverip", "192.168.1.1"),
+        ] {
+            environ.insert(key.into(), value.into());
+        }
+
+        // pseudo random content
+        let mut seed = 0x1234_5678u32;
+        let flash = (0..256 << 10)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+
+        Self {
+            prompt: "hisilicon # ".into(),
+            version: "2016.11-g2fc5f58-dirty".into(),
+            power_on: None,
+            autoboot: None,
+            autoboot_prompt: None,
+            tick: Duration::from_secs(1),
+            environ,
+            ram_base: 0x4000_0000,
+            ram_size: 0x0400_0000,
+            flash_nand: false,
+            flash_name: "XM_FM25Q64".into(),
+            flash_id: [0xa1, 0x40, 0x17],
+            flash_block: 64 << 10,
+            flash,
+        }
+    }
+
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = prompt.into();
+        self
+    }
+
+    /// Version string without `U-Boot` prefix
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// Boot after delay instead of sitting at prompt (input is discarded until that)
+    pub fn power_on_delay(mut self, delay: Duration) -> Self {
+        self.power_on = Some(delay);
+        self
+    }
+
+    /// Boot with autoboot countdown which can be stopped by key
+    ///
+    /// Zero seconds means that pending input is checked once after single tick.
+    pub fn autoboot(mut self, seconds: u32, key: TerminalKey) -> Self {
+        self.power_on.get_or_insert(Duration::ZERO);
+        self.autoboot = Some((seconds, key));
+        self
+    }
+
+    /// Print custom autoboot prompt once instead of countdown (`{}` is replaced by seconds)
+    pub fn autoboot_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.autoboot_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Duration of single second of countdown
+    pub fn tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.environ.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn ram(mut self, base: u64, size: u64) -> Self {
+        self.ram_base = base;
+        self.ram_size = size;
+        self
+    }
+
+    pub fn nand(mut self, nand: bool) -> Self {
+        self.flash_nand = nand;
+        self
+    }
+
+    pub fn flash_image(mut self, image: impl Into<Vec<u8>>) -> Self {
+        self.flash = image.into();
+        self
+    }
+
+    /// Backing flash image
+    pub fn flash(&self) -> &[u8] {
+        &self.flash
+    }
+
+    /// Run device on in-memory stream and get host side of it
+    pub fn spawn(self) -> DuplexStream {
+        let (host, device) = tokio::io::duplex(64 << 10);
+        tokio::spawn(self.run(device));
+        host
+    }
+
+    /// Run device on specified stream until it closed
+    pub async fn run(self, stream: impl AsyncRead + AsyncWrite) -> io::Result<()> {
+        let (rx, tx) = tokio::io::split(stream);
+        let mut device = Device {
+            sim: self,
+            rx,
+            tx,
+            ram: Vec::new(),
+            probed: false,
+            rc: 0,
+            baud_wait: false,
+        };
+        device.run().await
+    }
+}
+
+struct Device<R, W> {
+    sim: Simulator,
+    rx: R,
+    tx: W,
+    /** Written RAM regions */
+    ram: Vec<(u64, Vec<u8>)>,
+    /** SPI flash probed */
+    probed: bool,
+    /** Last return code */
+    rc: i32,
+    /** Baud rate is switched, waiting ENTER */
+    baud_wait: bool,
+}
+
+impl<R, W> Device<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    async fn run(&mut self) -> io::Result<()> {
+        if let Some(delay) = self.sim.power_on {
+            tokio::time::sleep(delay).await;
+            self.discard_input().await?;
+
+            let banner = format!("\r\n\r\nU-Boot {}\r\n\r\n", self.sim.version);
+            self.tx.write_all(banner.as_bytes()).await?;
+
+            if let Some((seconds, key)) = self.sim.autoboot.clone() {
+                if !self.countdown(seconds, key).await? {
+                    self.tx.write_all(b"Starting kernel ...\r\n\r\n").await?;
+                    // kernel does not read console
+                    let mut buf = [0u8; 256];
+                    while self.rx.read(&mut buf).await? > 0 {}
+                    return Ok(());
+                }
+            }
+
+            self.tx.write_all(self.sim.prompt.as_bytes()).await?;
+        }
+
+        self.shell().await
+    }
+
+    async fn discard_input(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 256];
+        while let Ok(res) = tokio::time::timeout(Duration::ZERO, self.rx.read(&mut buf)).await {
+            if res? == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true when autoboot was stopped
+    async fn countdown(&mut self, seconds: u32, key: TerminalKey) -> io::Result<bool> {
+        fn name(key: &TerminalKey) -> String {
+            match key {
+                TerminalKey::Any => "any key".into(),
+                TerminalKey::Key(chr) => chr.to_string(),
+                TerminalKey::Ctrl(code) => format!("ctrl+{}", code.to_ascii_lowercase() as char),
+                TerminalKey::Esc => "<Esc>".into(),
+                TerminalKey::Sequence(keys) => keys.iter().map(name).collect(),
+            }
+        }
+        let stop = match &key {
+            TerminalKey::Any => None,
+            key => key.encode().ok().map(String::into_bytes),
+        };
+        let mut received = Vec::new();
+        let mut buf = [0u8; 256];
+
+        // bootdelay=0 checks pending input once without any prompt
+        if seconds == 0 {
+            tokio::time::sleep(self.sim.tick).await;
+            while let Ok(res) = tokio::time::timeout(Duration::ZERO, self.rx.read(&mut buf)).await {
+                let len = res?;
+                if len == 0 {
+                    break;
+                }
+                received.extend_from_slice(&buf[..len]);
+            }
+            return Ok(is_stopped(&received, stop.as_deref()));
+        }
+
+        let keyed = matches!(key, TerminalKey::Esc | TerminalKey::Sequence(_));
+        if keyed || self.sim.autoboot_prompt.is_some() {
+            let msg = match &self.sim.autoboot_prompt {
+                Some(prompt) => prompt.replace("{}", &seconds.to_string()),
+                None => format!(
+                    "Autobooting in {} seconds, press \"{}\" to stop",
+                    seconds,
+                    name(&key)
+                ),
+            };
+            self.tx.write_all(msg.as_bytes()).await?;
+            self.tx.write_all(b"\r\n").await?;
+
+            let deadline = tokio::time::Instant::now() + self.sim.tick * seconds;
+            while let Ok(res) = tokio::time::timeout_at(deadline, self.rx.read(&mut buf)).await {
+                let len = res?;
+                if len == 0 {
+                    return Ok(false);
+                }
+                received.extend_from_slice(&buf[..len]);
+                if is_stopped(&received, stop.as_deref()) {
+                    return Ok(true);
+                }
+            }
+            return Ok(false);
+        }
+
+        let msg = format!("Hit {} to stop autoboot: {:2} ", name(&key), seconds);
+        self.tx.write_all(msg.as_bytes()).await?;
+
+        for remain in (0..seconds).rev() {
+            let deadline = tokio::time::Instant::now() + self.sim.tick;
+            while let Ok(res) = tokio::time::timeout_at(deadline, self.rx.read(&mut buf)).await {
+                let len = res?;
+                if len == 0 {
+                    return Ok(false);
+                }
+                received.extend_from_slice(&buf[..len]);
+                if is_stopped(&received, stop.as_deref()) {
+                    self.tx.write_all(b"\x08\x08\x08 0 \r\n").await?;
+                    return Ok(true);
+                }
+            }
+            let msg = format!("\x08\x08\x08{:2} ", remain);
+            self.tx.write_all(msg.as_bytes()).await?;
+        }
+        self.tx.write_all(b"\r\n").await?;
+
+        Ok(false)
+    }
+
+    async fn shell(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        let mut cr = false;
+        let mut buf = [0u8; 256];
+
+        loop {
+            let len = self.rx.read(&mut buf).await?;
+            if len == 0 {
+                return Ok(());
+            }
+
+            let mut out = Vec::new();
+            for &byte in &buf[..len] {
+                if self.baud_wait {
+                    if byte == b'\r' {
+                        self.baud_wait = false;
+                        out.extend(self.sim.prompt.as_bytes());
+                    }
+                    continue;
+                }
+                match byte {
+                    // CR LF is single enter
+                    b'\n' if cr => (),
+                    b'\r' | b'\n' => {
+                        out.extend(b"\r\n");
+                        self.execute(&line, &mut out).await;
+                        if !self.baud_wait {
+                            out.extend(self.sim.prompt.as_bytes());
+                        }
+                        line.clear();
+                    }
+                    // Ctrl-C
+                    0x03 => {
+                        out.extend(b"<INTERRUPT>\r\n");
+                        out.extend(self.sim.prompt.as_bytes());
+                        line.clear();
+                    }
+                    0x08 | 0x7f if !line.is_empty() => {
+                        line.pop();
+                        out.extend(b"\x08 \x08");
+                    }
+                    0x20..=0x7e => {
+                        line.push(byte as char);
+                        out.push(byte);
+                    }
+                    _ => (),
+                }
+                cr = byte == b'\r';
+            }
+            self.tx.write_all(&out).await?;
+        }
+    }
+
+    fn expand(&self, src: &str) -> String {
+        let mut out = String::new();
+        let mut chars = src.chars().peekable();
+        while let Some(chr) = chars.next() {
+            if chr != '$' {
+                out.push(chr);
+                continue;
+            }
+            let name: String = match chars.peek() {
+                Some('?') => {
+                    chars.next();
+                    out.push_str(&self.rc.to_string());
+                    continue;
+                }
+                Some('{') => {
+                    chars.next();
+                    chars.by_ref().take_while(|chr| *chr != '}').collect()
+                }
+                _ => {
+                    let mut name = String::new();
+                    while let Some(chr) = chars.next_if(|chr| chr.is_alphanumeric() || *chr == '_')
+                    {
+                        name.push(chr);
+                    }
+                    name
+                }
+            };
+            if let Some(value) = self.sim.environ.get(&name) {
+                out.push_str(value);
+            }
+        }
+        out
+    }
+
+    async fn execute(&mut self, line: &str, out: &mut Vec<u8>) {
+        for cmd in line.split(';') {
+            let cmd = self.expand(cmd);
+            let args: Vec<&str> = cmd.split_whitespace().collect();
+            if args.is_empty() {
+                continue;
+            }
+            self.rc = match self.command(&args, out).await {
+                Ok(()) => 0,
+                Err(msg) => {
+                    out.extend(msg.as_bytes());
+                    out.extend(b"\r\n");
+                    1
+                }
+            };
+        }
+    }
+
+    async fn command(&mut self, args: &[&str], out: &mut Vec<u8>) -> Result<(), String> {
+        fn println(out: &mut Vec<u8>, line: impl AsRef<str>) {
+            out.extend(line.as_ref().as_bytes());
+            out.extend(b"\r\n");
+        }
+
+        fn hex(src: Option<&&str>) -> Result<u64, String> {
+            let src = src.ok_or("Usage: missing argument")?;
+            let digits = src.trim_start_matches("0x").trim_start_matches("0X");
+            u64::from_str_radix(digits, 16).map_err(|_| format!("Invalid number: {}", src))
+        }
+
+        fn size(val: u64) -> String {
+            if val % (1 << 20) == 0 {
+                format!("{}MB", val >> 20)
+            } else {
+                format!("{}KB", val >> 10)
+            }
+        }
+
+        match args {
+            ["version"] => println(out, format!("U-Boot {}", self.sim.version)),
+            ["echo", words @ ..] => println(out, words.join(" ")),
+            ["printenv"] => {
+                let mut env_size = 0;
+                for (key, value) in &self.sim.environ {
+                    println(out, format!("{}={}", key, value));
+                    env_size += key.len() + value.len() + 2;
+                }
+                println(out, "");
+                println(out, format!("Environment size: {}/65532 bytes", env_size));
+            }
+            ["printenv", names @ ..] => {
+                for name in names {
+                    let value = self
+                        .sim
+                        .environ
+                        .get(*name)
+                        .ok_or_else(|| format!("## Error: \"{}\" not defined", name))?;
+                    println(out, format!("{}={}", name, value));
+                }
+            }
+            ["setenv", "baudrate", value] => {
+                let rate = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|rate| {
+                        [9600, 19200, 38400, 57600, 115200, 230400, 921600].contains(rate)
+                    })
+                    .ok_or_else(|| format!("## Baudrate {} bps not supported", value))?;
+                println(
+                    out,
+                    format!("## Switch baudrate to {} bps and press ENTER ...", rate),
+                );
+                self.sim.environ.insert("baudrate".into(), rate.to_string());
+                self.baud_wait = true;
+            }
+            ["setenv", name] => {
+                self.sim.environ.shift_remove(*name);
+            }
+            ["setenv", name, value @ ..] => {
+                self.sim.environ.insert(name.to_string(), value.join(" "));
+            }
+            ["bdinfo"] => {
+                let ip_addr = self.sim.environ.get("ipaddr").cloned();
+                let baudrate = self.sim.environ.get("baudrate").cloned();
+                for (key, value) in [
+                    ("arch_number", "0x00001F40".into()),
+                    (
+                        "boot_params",
+                        format!("0x{:08X}", self.sim.ram_base + 0x100),
+                    ),
+                    ("DRAM bank", "0x00000000".into()),
+                    ("-> start", format!("0x{:08X}", self.sim.ram_base)),
+                    ("-> size", format!("0x{:08X}", self.sim.ram_size)),
+                    ("eth0name", "eth0".into()),
+                    ("current eth", "eth0".into()),
+                    ("ip_addr", ip_addr.unwrap_or_else(|| "<NULL>".into())),
+                    ("baudrate", format!("{} bps", baudrate.unwrap_or_default())),
+                ] {
+                    println(out, format!("{:<12}= {}", key, value));
+                }
+            }
+            ["getinfo", "version"] => {
+                println(out, format!("version: U-Boot {}", self.sim.version));
+            }
+            ["getinfo", "bootmode"] => {
+                println(out, if self.sim.flash_nand { "nand" } else { "spi" });
+            }
+            ["getinfo", "spi"] if !self.sim.flash_nand => self.flash_info(out, size),
+            ["getinfo", "nand"] if self.sim.flash_nand => self.flash_info(out, size),
+            ["sf", "probe", ..] => {
+                self.probed = true;
+                println(
+                    out,
+                    format!(
+                        "SF: Detected {} with page size 256 Bytes, erase size {} KiB, total {}",
+                        self.sim.flash_name,
+                        self.sim.flash_block >> 10,
+                        size(self.sim.flash.len() as u64)
+                    ),
+                );
+            }
+            ["sf", "read", addr, offset, len] => {
+                if !self.probed {
+                    return Err("No SPI flash selected. Please run `sf probe'".into());
+                }
+                let (addr, offset, len) = (hex(Some(addr))?, hex(Some(offset))?, hex(Some(len))?);
+                let data = self
+                    .sim
+                    .flash
+                    .get(offset as usize..(offset + len) as usize)
+                    .ok_or("ERROR: attempting read past flash size")?
+                    .to_vec();
+                self.ram_write(addr, data)?;
+                println(out, format!("SF: {} bytes @ {:#x} Read: OK", len, offset));
+            }
+            ["crc32", addr, len] => {
+                let (addr, len) = (hex(Some(addr))?, hex(Some(len))?);
+                let crc = crc32fast::hash(&self.ram_read(addr, len));
+                println(
+                    out,
+                    format!(
+                        "crc32 for {:08x} ... {:08x} ==> {:08x}",
+                        addr,
+                        addr + len - 1,
+                        crc
+                    ),
+                );
+            }
+            ["md.b", addr, len] => {
+                let (addr, len) = (hex(Some(addr))?, hex(Some(len))?);
+                let data = self.ram_read(addr, len);
+                for (index, chunk) in data.chunks(16).enumerate() {
+                    let mut line = format!("{:08x}:", addr + index as u64 * 16);
+                    for byte in chunk {
+                        line.push_str(&format!(" {:02x}", byte));
+                    }
+                    for _ in chunk.len()..16 {
+                        line.push_str("   ");
+                    }
+                    line.push_str("    ");
+                    line.extend(chunk.iter().map(|byte| match byte {
+                        0x20..=0x7e => *byte as char,
+                        _ => '.',
+                    }));
+                    println(out, line);
+                }
+            }
+            ["tftpput", addr, len, name] => {
+                let (addr, len) = (hex(Some(addr))?, hex(Some(len))?);
+                let data = self.ram_read(addr, len);
+                self.tftp_put(name, &data, out).await?;
+            }
+            ["tftpboot", addr, name] => {
+                let addr = hex(Some(addr))?;
+                let data = self.tftp_get(name, addr, out).await?;
+                self.sim
+                    .environ
+                    .insert("filesize".into(), format!("{:x}", data.len()));
+                self.ram_write(addr, data)?;
+            }
+            [cmd, ..] => return Err(format!("Unknown command '{}' - try 'help'", cmd)),
+            [] => (),
+        }
+
+        Ok(())
+    }
+
+    fn flash_info(&self, out: &mut Vec<u8>, size: fn(u64) -> String) {
+        let [a, b, c] = self.sim.flash_id;
+        let lines = [
+            format!(
+                "Block:{} Chip:{}*1",
+                size(self.sim.flash_block),
+                size(self.sim.flash.len() as u64)
+            ),
+            format!("ID:{:#04X} {:#04X} {:#04X}", a, b, c).replace("0X", "0x"),
+            format!("Name:\"{}\"", self.sim.flash_name),
+        ];
+        for line in lines {
+            out.extend(line.as_bytes());
+            out.extend(b"\r\n");
+        }
+    }
+
+    /// Network addresses of device and TFTP server
+    fn tftp_addrs(&self) -> Result<(IpAddr, SocketAddr), String> {
+        let ip = |name: &str| {
+            self.sim
+                .environ
+                .get(name)
+                .and_then(|ip| ip.parse::<IpAddr>().ok())
+                .ok_or_else(|| format!("*** ERROR: `{}' not set", name))
+        };
+        let port = self
+            .sim
+            .environ
+            .get("tftpdstp")
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(TFTP_PORT);
+        Ok((ip("ipaddr")?, SocketAddr::new(ip("serverip")?, port)))
+    }
+
+    /// Upload data to TFTP server
+    async fn tftp_put(&mut self, name: &str, data: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        let (local_ip, server) = self.tftp_addrs()?;
+
+        out.extend(b"Using eth0 device\r\n");
+        out.extend(
+            format!(
+                "TFTP to server {}; our IP address is {}\r\n",
+                server.ip(),
+                local_ip
+            )
+            .as_bytes(),
+        );
+        out.extend(format!("Filename '{}'.\r\n", name).as_bytes());
+        out.extend(format!("Save size:    {:#x}\r\n", data.len()).as_bytes());
+        out.extend(b"Saving: ");
+
+        let socket = UdpSocket::bind((local_ip, 0))
+            .await
+            .map_err(|error| error.to_string())?;
+
+        let mut request = vec![0, TFTP_WRQ];
+        request.extend(name.as_bytes());
+        request.extend(b"\0octet\0");
+        let mut peer = server;
+        let mut packet = request;
+
+        // last block is shorter than block size (may be empty)
+        let blocks = data.len() / TFTP_BLOCK_SIZE + 1;
+        for block in 0..=blocks {
+            let reply = self.tftp_exchange(&socket, &mut peer, &packet, out).await?;
+            match reply.as_slice() {
+                [0, TFTP_ACK, hi, lo] if u16::from_be_bytes([*hi, *lo]) == block as u16 => (),
+                _ => return Err(tftp_error(&reply)),
+            }
+            if block == blocks {
+                break;
+            }
+
+            let start = block * TFTP_BLOCK_SIZE;
+            let end = data.len().min(start + TFTP_BLOCK_SIZE);
+            packet = vec![0, TFTP_DATA];
+            packet.extend(((block + 1) as u16).to_be_bytes());
+            packet.extend(&data[start..end]);
+
+            if block % 10 == 0 {
+                out.push(b'#');
+            }
+            if (block + 1) % (10 * 65) == 0 {
+                out.extend(b"\r\n\t ");
+            }
+        }
+
+        out.extend(b"\r\ndone\r\n");
+        out.extend(
+            format!(
+                "Bytes transferred = {} ({:x} hex)\r\n",
+                data.len(),
+                data.len()
+            )
+            .as_bytes(),
+        );
+        Ok(())
+    }
+
+    /// Download data from TFTP server
+    async fn tftp_get(
+        &mut self,
+        name: &str,
+        addr: u64,
+        out: &mut Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        let (local_ip, server) = self.tftp_addrs()?;
+
+        out.extend(b"Using eth0 device\r\n");
+        out.extend(
+            format!(
+                "TFTP from server {}; our IP address is {}\r\n",
+                server.ip(),
+                local_ip
+            )
+            .as_bytes(),
+        );
+        out.extend(format!("Filename '{}'.\r\n", name).as_bytes());
+        out.extend(format!("Load address: {:#x}\r\n", addr).as_bytes());
+        out.extend(b"Loading: ");
+
+        let socket = UdpSocket::bind((local_ip, 0))
+            .await
+            .map_err(|error| error.to_string())?;
+
+        let mut packet = vec![0, TFTP_RRQ];
+        packet.extend(name.as_bytes());
+        packet.extend(b"\0octet\0");
+        let mut peer = server;
+
+        let mut data = Vec::new();
+        for block in 1u16.. {
+            let reply = self.tftp_exchange(&socket, &mut peer, &packet, out).await?;
+            let chunk = match reply.as_slice() {
+                [0, TFTP_DATA, hi, lo, chunk @ ..] if u16::from_be_bytes([*hi, *lo]) == block => {
+                    chunk
+                }
+                _ => return Err(tftp_error(&reply)),
+            };
+            data.extend(chunk);
+
+            packet = vec![0, TFTP_ACK];
+            packet.extend(block.to_be_bytes());
+
+            if (block - 1) % 10 == 0 {
+                out.push(b'#');
+            }
+            if block % (10 * 65) == 0 {
+                out.extend(b"\r\n\t ");
+            }
+
+            // last block is shorter than block size (may be empty)
+            if chunk.len() < TFTP_BLOCK_SIZE {
+                socket
+                    .send_to(&packet, peer)
+                    .await
+                    .map_err(|error| error.to_string())?;
+                break;
+            }
+        }
+
+        out.extend(b"\r\ndone\r\n");
+        out.extend(
+            format!(
+                "Bytes transferred = {} ({:x} hex)\r\n",
+                data.len(),
+                data.len()
+            )
+            .as_bytes(),
+        );
+        Ok(data)
+    }
+
+    /// Send packet and wait reply with retries
+    async fn tftp_exchange(
+        &self,
+        socket: &UdpSocket,
+        peer: &mut SocketAddr,
+        packet: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        let mut buf = vec![0u8; TFTP_BLOCK_SIZE + 4];
+        for _ in 0..TFTP_RETRIES {
+            socket
+                .send_to(packet, *peer)
+                .await
+                .map_err(|error| error.to_string())?;
+            match tokio::time::timeout(TFTP_TIMEOUT, socket.recv_from(&mut buf)).await {
+                Ok(Ok((len, from))) => {
+                    // server answers from transfer port
+                    *peer = from;
+                    return Ok(buf[..len].to_vec());
+                }
+                Ok(Err(error)) => return Err(error.to_string()),
+                Err(_) => out.extend(b"T "),
+            }
+        }
+        Err("\r\nRetry count exceeded; starting again".into())
+    }
+
+    fn ram_write(&mut self, addr: u64, data: Vec<u8>) -> Result<(), String> {
+        if addr < self.sim.ram_base
+            || addr + data.len() as u64 > self.sim.ram_base + self.sim.ram_size
+        {
+            return Err(format!("Address {:#x} is out of RAM", addr));
+        }
+        self.ram.push((addr, data));
+        Ok(())
+    }
+
+    fn ram_read(&self, addr: u64, len: u64) -> Vec<u8> {
+        let mut data = vec![0u8; len as usize];
+        // later writes overlaps earlier ones
+        for (base, chunk) in &self.ram {
+            let start = addr.max(*base);
+            let end = (addr + len).min(base + chunk.len() as u64);
+            if start < end {
+                data[(start - addr) as usize..(end - addr) as usize]
+                    .copy_from_slice(&chunk[(start - base) as usize..(end - base) as usize]);
+            }
+        }
+        data
+    }
+}
+
+/// Describe unexpected TFTP reply
+fn tftp_error(reply: &[u8]) -> String {
+    match reply {
+        [0, TFTP_ERROR, _, code, msg @ ..] => format!(
+            "TFTP error: '{}' ({})",
+            String::from_utf8_lossy(msg).trim_end_matches('\0'),
+            code
+        ),
+        _ => "TFTP error: unexpected packet".into(),
+    }
+}
+
+/// Input contains stop sequence (any input when it is not set)
+fn is_stopped(received: &[u8], stop: Option<&[u8]>) -> bool {
+    match stop {
+        None => !received.is_empty(),
+        Some(stop) => received.windows(stop.len()).any(|window| window == stop),
+    }
+}
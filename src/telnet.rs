@@ -0,0 +1,499 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::io;
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+/// Telnet commands
+pub(crate) mod cmd {
+    pub const SE: u8 = 240;
+    pub const SB: u8 = 250;
+    pub const WILL: u8 = 251;
+    pub const WONT: u8 = 252;
+    pub const DO: u8 = 253;
+    pub const DONT: u8 = 254;
+    pub const IAC: u8 = 255;
+}
+
+/// Telnet options
+pub(crate) mod opt {
+    pub const BINARY: u8 = 0;
+    pub const ECHO: u8 = 1;
+    pub const SGA: u8 = 3;
+}
+
+const RX_CHUNK: usize = 1024;
+const SUB_LIMIT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /** Plain data */
+    Data,
+    /** Got IAC */
+    Iac,
+    /** Got IAC WILL/WONT/DO/DONT */
+    Option(u8),
+    /** Inside subnegotiation */
+    Sub,
+    /** Got IAC inside subnegotiation */
+    SubIac,
+}
+
+/// Option negotiation state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OptState {
+    /** Disabled */
+    No,
+    /** Enabling is requested but not confirmed yet */
+    WantYes,
+    /** Enabled */
+    Yes,
+}
+
+/// Telnet protocol state
+#[derive(Debug)]
+pub(crate) struct Codec {
+    state: State,
+    /** Previous data byte was CR */
+    cr: bool,
+    /** Options which we are allowed to enable locally */
+    local_supported: Vec<u8>,
+    /** Options which we are allowed to enable remotely */
+    remote_supported: Vec<u8>,
+    /** Local options state */
+    local: [OptState; 256],
+    /** Remote options state */
+    remote: [OptState; 256],
+    /** Subnegotiation which is receiving now */
+    sub_buf: Vec<u8>,
+    /** Received subnegotiations of supported options */
+    pub(crate) subs: Vec<Vec<u8>>,
+    /** Encoded subnegotiations of local options which wait for enabling */
+    pending_subs: Vec<(u8, Vec<u8>)>,
+}
+
+impl Codec {
+    pub(crate) fn new(local_supported: &[u8], remote_supported: &[u8]) -> Self {
+        Self {
+            state: State::Data,
+            cr: false,
+            local_supported: local_supported.into(),
+            remote_supported: remote_supported.into(),
+            local: [OptState::No; 256],
+            remote: [OptState::No; 256],
+            sub_buf: Vec::new(),
+            subs: Vec::new(),
+            pending_subs: Vec::new(),
+        }
+    }
+
+    /// Request enabling of options
+    ///
+    /// Options are treated as enabled only when peer agrees.
+    pub(crate) fn negotiate(&mut self, local: &[u8], remote: &[u8], out: &mut Vec<u8>) {
+        for &opt in local {
+            if self.local[opt as usize] == OptState::No {
+                self.local[opt as usize] = OptState::WantYes;
+                out.extend([cmd::IAC, cmd::WILL, opt]);
+            }
+        }
+        for &opt in remote {
+            if self.remote[opt as usize] == OptState::No {
+                self.remote[opt as usize] = OptState::WantYes;
+                out.extend([cmd::IAC, cmd::DO, opt]);
+            }
+        }
+    }
+
+    pub(crate) fn local_enabled(&self, opt: u8) -> bool {
+        self.local[opt as usize] == OptState::Yes
+    }
+
+    pub(crate) fn remote_enabled(&self, opt: u8) -> bool {
+        self.remote[opt as usize] == OptState::Yes
+    }
+
+    /// Send subnegotiation of local option
+    ///
+    /// It is queued until peer agrees to enable option and dropped when peer refuses.
+    pub(crate) fn send_sub(&mut self, opt: u8, payload: &[u8], out: &mut Vec<u8>) {
+        match self.local[opt as usize] {
+            OptState::Yes => encode_sub(opt, payload, out),
+            OptState::WantYes => {
+                let mut sub = Vec::new();
+                encode_sub(opt, payload, &mut sub);
+                self.pending_subs.push((opt, sub));
+            }
+            OptState::No => (),
+        }
+    }
+
+    /// Send or drop queued subnegotiations when option state is settled
+    fn release_subs(&mut self, opt: u8, out: &mut Vec<u8>) {
+        let enabled = self.local_enabled(opt);
+        self.pending_subs.retain(|(sub_opt, sub)| {
+            if *sub_opt != opt {
+                return true;
+            }
+            if enabled {
+                out.extend(sub);
+            }
+            false
+        });
+    }
+
+    /// Decode received data
+    ///
+    /// Negotiation replies are appended to `out`.
+    fn decode(&mut self, input: &[u8], data: &mut ReadBuf<'_>, out: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Data, cmd::IAC) => State::Iac,
+                (State::Data, byte) => {
+                    // CR NUL means bare CR in non-binary mode
+                    if !(self.cr && byte == 0 && !self.remote_enabled(opt::BINARY)) {
+                        data.put_slice(&[byte]);
+                    }
+                    self.cr = byte == b'\r';
+                    State::Data
+                }
+                (State::Iac, cmd::IAC) => {
+                    data.put_slice(&[cmd::IAC]);
+                    self.cr = false;
+                    State::Data
+                }
+                (State::Iac, cmd::WILL | cmd::WONT | cmd::DO | cmd::DONT) => State::Option(byte),
+                (State::Iac, cmd::SB) => {
+                    self.sub_buf.clear();
+                    State::Sub
+                }
+                // NOP, GA and other commands without arguments
+                (State::Iac, _) => State::Data,
+                (State::Option(verb), opt) => {
+                    self.option(verb, opt, out);
+                    State::Data
+                }
+                (State::Sub, cmd::IAC) => State::SubIac,
+                (State::Sub, byte) | (State::SubIac, byte @ cmd::IAC) => {
+                    if self.sub_buf.len() < SUB_LIMIT {
+                        self.sub_buf.push(byte);
+                    }
+                    State::Sub
+                }
+                (State::SubIac, cmd::SE) => {
+                    self.subnegotiation();
+                    State::Data
+                }
+                // broken subnegotiation
+                (State::SubIac, _) => State::Data,
+            };
+        }
+    }
+
+    fn subnegotiation(&mut self) {
+        // keep subnegotiations of supported options only
+        if let Some(opt) = self.sub_buf.first() {
+            if self.local_supported.contains(opt) || self.remote_supported.contains(opt) {
+                self.subs.push(core::mem::take(&mut self.sub_buf));
+            }
+        }
+    }
+
+    /// Handle option request or reply
+    ///
+    /// Answer is sent only when option state is changed (RFC 854 loop avoidance),
+    /// confirmation of own request and refusal of disabled option need no answer.
+    fn option(&mut self, verb: u8, opt: u8, out: &mut Vec<u8>) {
+        let idx = opt as usize;
+        match verb {
+            cmd::DO => match self.local[idx] {
+                OptState::No if self.local_supported.contains(&opt) => {
+                    self.local[idx] = OptState::Yes;
+                    out.extend([cmd::IAC, cmd::WILL, opt]);
+                }
+                OptState::No => out.extend([cmd::IAC, cmd::WONT, opt]),
+                OptState::WantYes => {
+                    self.local[idx] = OptState::Yes;
+                    self.release_subs(opt, out);
+                }
+                OptState::Yes => (),
+            },
+            cmd::DONT => match self.local[idx] {
+                OptState::Yes => {
+                    self.local[idx] = OptState::No;
+                    out.extend([cmd::IAC, cmd::WONT, opt]);
+                }
+                OptState::WantYes => {
+                    self.local[idx] = OptState::No;
+                    self.release_subs(opt, out);
+                }
+                OptState::No => (),
+            },
+            cmd::WILL => match self.remote[idx] {
+                OptState::No if self.remote_supported.contains(&opt) => {
+                    self.remote[idx] = OptState::Yes;
+                    out.extend([cmd::IAC, cmd::DO, opt]);
+                }
+                OptState::No => out.extend([cmd::IAC, cmd::DONT, opt]),
+                OptState::WantYes => self.remote[idx] = OptState::Yes,
+                OptState::Yes => (),
+            },
+            cmd::WONT => match self.remote[idx] {
+                OptState::Yes => {
+                    self.remote[idx] = OptState::No;
+                    out.extend([cmd::IAC, cmd::DONT, opt]);
+                }
+                OptState::WantYes => self.remote[idx] = OptState::No,
+                OptState::No => (),
+            },
+            _ => (),
+        }
+    }
+
+    /// Encode data to send
+    fn encode(&self, input: &[u8], out: &mut Vec<u8>) {
+        let binary = self.local_enabled(opt::BINARY);
+        for &byte in input {
+            match byte {
+                cmd::IAC => out.extend([cmd::IAC, cmd::IAC]),
+                // bare CR must be followed by NUL in non-binary mode
+                b'\r' if !binary => out.extend([b'\r', 0]),
+                byte => out.push(byte),
+            }
+        }
+    }
+}
+
+/// Encode subnegotiation
+pub(crate) fn encode_sub(opt: u8, payload: &[u8], out: &mut Vec<u8>) {
+    out.extend([cmd::IAC, cmd::SB, opt]);
+    for &byte in payload {
+        if byte == cmd::IAC {
+            out.push(cmd::IAC);
+        }
+        out.push(byte);
+    }
+    out.extend([cmd::IAC, cmd::SE]);
+}
+
+/// Telnet client stream
+///
+/// Handles IAC escaping and negotiates binary mode and suppress go-ahead
+/// so the console data passes through unchanged.
+pub struct TelnetStream<S> {
+    inner: S,
+    pub(crate) codec: Codec,
+    /** Encoded data and control replies which isn't sent yet */
+    pub(crate) out_buf: Vec<u8>,
+}
+
+pub(crate) async fn connect_tcp(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+impl TelnetStream<TcpStream> {
+    /// Connect to telnet server
+    pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let mut stream = Self::new(connect_tcp(addr).await?);
+        stream.flush().await?;
+        Ok(stream)
+    }
+}
+
+impl<S> TelnetStream<S> {
+    /// Wrap stream and queue initial options negotiation
+    pub fn new(inner: S) -> Self {
+        // server echo is accepted but never requested
+        let codec = Codec::new(
+            &[opt::BINARY, opt::SGA],
+            &[opt::BINARY, opt::SGA, opt::ECHO],
+        );
+        Self::with_codec(
+            inner,
+            codec,
+            &[opt::BINARY, opt::SGA],
+            &[opt::BINARY, opt::SGA],
+        )
+    }
+
+    pub(crate) fn with_codec(inner: S, mut codec: Codec, local: &[u8], remote: &[u8]) -> Self {
+        let mut out_buf = Vec::new();
+        codec.negotiate(local, remote, &mut out_buf);
+        Self {
+            inner,
+            codec,
+            out_buf,
+        }
+    }
+
+    /// Get inner stream
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> TelnetStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.out_buf.is_empty() {
+            let len = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out_buf))?;
+            if len == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.out_buf.drain(..len);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncRead for TelnetStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // pending replies should not wait for next write
+        if let Poll::Ready(Err(error)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        let mut raw = [0u8; RX_CHUNK];
+        loop {
+            let len = buf.remaining().min(RX_CHUNK);
+            let mut raw_buf = ReadBuf::new(&mut raw[..len]);
+            futures::ready!(Pin::new(&mut this.inner).poll_read(cx, &mut raw_buf))?;
+            let input = raw_buf.filled();
+
+            if input.is_empty() {
+                // end of stream
+                return Poll::Ready(Ok(()));
+            }
+
+            let filled = buf.filled().len();
+            this.codec.decode(input, buf, &mut this.out_buf);
+
+            if let Poll::Ready(Err(error)) = this.poll_drain(cx) {
+                return Poll::Ready(Err(error));
+            }
+
+            if buf.filled().len() > filled {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<S> AsyncWrite for TelnetStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        futures::ready!(this.poll_drain(cx))?;
+        this.codec.encode(data, &mut this.out_buf);
+
+        if let Poll::Ready(Err(error)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        futures::ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        futures::ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn negotiate_and_escape() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            let mut buf = [0u8; 12];
+            socket.read_exact(&mut buf).await.unwrap();
+            let expected = [
+                [cmd::IAC, cmd::WILL, opt::BINARY],
+                [cmd::IAC, cmd::WILL, opt::SGA],
+                [cmd::IAC, cmd::DO, opt::BINARY],
+                [cmd::IAC, cmd::DO, opt::SGA],
+            ];
+            assert_eq!(&buf[..], &expected.concat()[..]);
+
+            let data = [
+                &b"a"[..],
+                &[cmd::IAC, cmd::DO, opt::BINARY],
+                &[cmd::IAC, cmd::DONT, opt::SGA],
+                &[cmd::IAC, cmd::WONT, opt::SGA],
+                &[cmd::IAC, cmd::DONT, 24],
+                &[cmd::IAC, cmd::WILL, opt::ECHO],
+                b"b",
+                &[cmd::IAC, cmd::IAC],
+                &[cmd::IAC, cmd::DO, 24],
+                b"c",
+            ];
+            socket.write_all(&data.concat()).await.unwrap();
+
+            let mut buf = [0u8; 6];
+            socket.read_exact(&mut buf).await.unwrap();
+            let expected = [[cmd::IAC, cmd::DO, opt::ECHO], [cmd::IAC, cmd::WONT, 24]];
+            assert_eq!(&buf[..], &expected.concat()[..]);
+
+            let mut buf = [0u8; 4];
+            socket.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, &[b'x', cmd::IAC, cmd::IAC, b'\r']);
+        });
+
+        let mut stream = TelnetStream::connect(addr).await.unwrap();
+
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, &[b'a', b'b', cmd::IAC, b'c']);
+
+        assert!(stream.codec.local_enabled(opt::BINARY));
+        assert!(!stream.codec.local_enabled(opt::SGA));
+        assert!(!stream.codec.remote_enabled(opt::BINARY));
+        assert!(stream.codec.remote_enabled(opt::ECHO));
+
+        stream.write_all(&[b'x', cmd::IAC, b'\r']).await.unwrap();
+        stream.flush().await.unwrap();
+
+        server.await.unwrap();
+    }
+}
@@ -1,10 +1,15 @@
-use crate::Result;
+use core::str::FromStr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TerminalKey {
     Any,
     Key(char),
     Ctrl(u8),
+    Esc,
+    /// Keys which is sent one after another (`<Esc><Esc>`, stop string, password)
+    Sequence(Vec<TerminalKey>),
 }
 
 impl TerminalKey {
@@ -15,60 +20,182 @@ impl TerminalKey {
             Self::Ctrl(code) => {
                 unsafe { char::from_u32_unchecked((code - (b'A' - 0x1)) as u32) }.to_string()
             }
+            Self::Esc => '\x1b'.to_string(),
+            Self::Sequence(keys) => keys.iter().map(|key| key.encode()).collect::<Result<_>>()?,
         })
     }
 
+    fn from_keys(mut keys: Vec<TerminalKey>) -> Self {
+        if keys.len() == 1 {
+            keys.pop().unwrap()
+        } else {
+            Self::Sequence(keys)
+        }
+    }
+
+    /// Parse keys notation (`tpl`, `<Esc><Esc>`, `<Ctrl-C>`, `secret<Enter>`)
+    pub fn parse_keys(src: impl AsRef<str>) -> Result<Self> {
+        use nom::combinator::all_consuming;
+
+        let (_, key) = all_consuming(parse_keys(None))(src.as_ref())
+            .map_err(|_| Error::parse("keys", src.as_ref()))?;
+        Ok(key)
+    }
+
+    /// Find autoboot stop hint in line
+    ///
+    /// Recognized hints looks like `Hit any key to stop autoboot`, `Press Ctrl+C to abort`
+    /// or `Autobooting in 3 seconds, press "<Esc><Esc>" to stop`.
     pub fn parse_stop_autoboot(src: impl AsRef<str>) -> Result<Self> {
         use nom::{
             branch::alt,
             bytes::complete::tag_no_case as tag,
-            character::complete::{one_of, satisfy, space1 as space},
+            character::complete::{char, satisfy, space1 as space},
             combinator::{map, value},
-            sequence::tuple,
+            sequence::{delimited, tuple},
             IResult,
         };
 
-        fn is_alpha(c: char) -> bool {
-            let c = c as u32;
-            if c > 255 {
-                false
-            } else {
-                let c = c as u8;
-                (c >= b'A' && c <= b'Z') || (c >= b'a' && c <= b'z')
-            }
-        }
-
         fn parse(input: &str) -> IResult<&str, TerminalKey> {
             map(
                 tuple((
-                    value((), tuple((alt((tag("hit"), tag("press"))), space))),
+                    value(
+                        (),
+                        tuple((
+                            alt((tag("hit"), tag("press"), tag("type"), tag("enter"))),
+                            space,
+                        )),
+                    ),
                     alt((
-                        map(
-                            tuple((tag("ctrl"), one_of("-+"), satisfy(is_alpha))),
-                            |(_, _, chr)| {
-                                let code = chr as u32 as u8;
-                                TerminalKey::Ctrl(if code < b'a' {
-                                    code
-                                } else {
-                                    code - (b'a' - b'A')
-                                })
-                            },
-                        ),
+                        parse_ctrl,
                         map(tuple((tag("any"), space, tag("key"))), |_| TerminalKey::Any),
-                        map(satisfy(is_alpha), |chr| TerminalKey::Key(chr)),
+                        delimited(char('"'), parse_keys(Some('"')), char('"')),
+                        delimited(char('\''), parse_keys(Some('\'')), char('\'')),
+                        delimited(char('<'), parse_key_name, char('>')),
+                        value(TerminalKey::Esc, alt((tag("escape"), tag("esc")))),
+                        map(satisfy(is_alpha), TerminalKey::Key),
                     )),
                     value(
                         (),
-                        tuple((space, tag("to"), space, tag("stop"), space, tag("autoboot"))),
+                        tuple((
+                            space,
+                            tag("to"),
+                            space,
+                            alt((tag("stop"), tag("abort"), tag("interrupt"))),
+                        )),
                     ),
                 )),
                 |(_, key, _)| key,
             )(input)
         }
 
-        let (_, key) =
-            parse(src.as_ref()).map_err(|err| anyhow::anyhow!("Invalid sequence: {}", err))?;
-        Ok(key)
+        let src = src.as_ref();
+        src.char_indices()
+            .find_map(|(index, _)| parse(&src[index..]).ok().map(|(_, key)| key))
+            .ok_or_else(|| Error::parse("autoboot prompt", src))
+    }
+}
+
+impl FromStr for TerminalKey {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Self> {
+        Self::parse_keys(src)
+    }
+}
+
+fn is_alpha(c: char) -> bool {
+    let c = c as u32;
+    if c > 255 {
+        false
+    } else {
+        let c = c as u8;
+        c.is_ascii_alphabetic()
+    }
+}
+
+/// `ctrl+c`, `Ctrl-C`
+fn parse_ctrl(input: &str) -> nom::IResult<&str, TerminalKey> {
+    use nom::{
+        bytes::complete::tag_no_case as tag,
+        character::complete::{one_of, satisfy},
+        combinator::map,
+        sequence::tuple,
+    };
+
+    map(
+        tuple((tag("ctrl"), one_of("-+"), satisfy(is_alpha))),
+        |(_, _, chr)| TerminalKey::Ctrl(chr.to_ascii_uppercase() as u32 as u8),
+    )(input)
+}
+
+/// Key name inside angle brackets
+fn parse_key_name(input: &str) -> nom::IResult<&str, TerminalKey> {
+    use nom::{branch::alt, bytes::complete::tag_no_case as tag, combinator::value};
+
+    alt((
+        parse_ctrl,
+        value(TerminalKey::Esc, alt((tag("escape"), tag("esc")))),
+        value(TerminalKey::Key('\r'), alt((tag("enter"), tag("cr")))),
+        value(TerminalKey::Key(' '), tag("space")),
+    ))(input)
+}
+
+/// Keys until closing quote
+fn parse_keys(quote: Option<char>) -> impl Fn(&str) -> nom::IResult<&str, TerminalKey> {
+    use nom::{
+        branch::alt,
+        character::complete::{char, satisfy},
+        combinator::map,
+        multi::many1,
+        sequence::delimited,
+    };
+
+    move |input| {
+        map(
+            many1(alt((
+                delimited(char('<'), parse_key_name, char('>')),
+                map(satisfy(|c| Some(c) != quote), TerminalKey::Key),
+            ))),
+            TerminalKey::from_keys,
+        )(input)
+    }
+}
+
+/// User defined autoboot stop rule
+///
+/// Needed for keyed autoboot and password prompts which do not tell the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutobootStop {
+    /// Text which appears in autoboot prompt line
+    pub pattern: String,
+    /// Keys to send when pattern is found
+    pub keys: TerminalKey,
+}
+
+impl AutobootStop {
+    pub fn new(pattern: impl Into<String>, keys: TerminalKey) -> Self {
+        Self {
+            pattern: pattern.into(),
+            keys,
+        }
+    }
+
+    pub fn matches(&self, line: &str) -> bool {
+        line.contains(&self.pattern)
+    }
+}
+
+impl FromStr for AutobootStop {
+    type Err = Error;
+
+    /// Parse `pattern=keys` (`Autoboot in=<Ctrl-C>`, `Password:=secret<Enter>`)
+    fn from_str(src: &str) -> Result<Self> {
+        let (pattern, keys) = src
+            .split_once('=')
+            .filter(|(pattern, _)| !pattern.is_empty())
+            .ok_or_else(|| Error::parse("autoboot stop rule", src))?;
+        Ok(Self::new(pattern, keys.parse()?))
     }
 }
 
@@ -99,4 +226,63 @@ mod test {
         let r = TerminalKey::parse_stop_autoboot("Hit Ctrl-D to stop autoboot").unwrap();
         assert_eq!(r, TerminalKey::Ctrl(b'D'));
     }
+
+    #[test]
+    fn stop_autoboot_by_ctrl_c_to_abort() {
+        let r = TerminalKey::parse_stop_autoboot("Press Ctrl+C to abort autoboot in 3 second(s)")
+            .unwrap();
+        assert_eq!(r, TerminalKey::Ctrl(b'C'));
+    }
+
+    #[test]
+    fn stop_autoboot_by_esc_esc() {
+        let r = TerminalKey::parse_stop_autoboot(
+            "Autobooting in 3 seconds, press \"<Esc><Esc>\" to stop",
+        )
+        .unwrap();
+        assert_eq!(
+            r,
+            TerminalKey::Sequence(vec![TerminalKey::Esc, TerminalKey::Esc])
+        );
+        assert_eq!(r.encode().unwrap(), "\x1b\x1b");
+    }
+
+    #[test]
+    fn stop_autoboot_by_string() {
+        let r =
+            TerminalKey::parse_stop_autoboot("Autoboot in 1 seconds, type 'tpl' to stop").unwrap();
+        assert_eq!(r.encode().unwrap(), "tpl");
+    }
+
+    #[test]
+    fn no_stop_autoboot() {
+        assert!(TerminalKey::parse_stop_autoboot("Autoboot in 1 seconds").is_err());
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!("x".parse::<TerminalKey>().unwrap(), TerminalKey::Key('x'));
+        assert_eq!(
+            "<ctrl-c>".parse::<TerminalKey>().unwrap(),
+            TerminalKey::Ctrl(b'C')
+        );
+        assert_eq!(
+            "pa<ss<Enter>"
+                .parse::<TerminalKey>()
+                .unwrap()
+                .encode()
+                .unwrap(),
+            "pa<ss\r"
+        );
+        assert!("".parse::<TerminalKey>().is_err());
+    }
+
+    #[test]
+    fn autoboot_stop_rule() {
+        let r = "Password:=secret<Enter>".parse::<AutobootStop>().unwrap();
+        assert_eq!(r.pattern, "Password:");
+        assert_eq!(r.keys.encode().unwrap(), "secret\r");
+        assert!(r.matches("Enter Password: "));
+        assert!("=x".parse::<AutobootStop>().is_err());
+    }
 }
@@ -1,16 +1,22 @@
 use async_tftp::packet;
 use async_tftp::server::Handler;
 use std::{
+    collections::HashSet,
     net::{IpAddr, SocketAddr},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 use tokio_util::compat::Compat;
 
+/// TFTP request handler which serves files of directory
+///
+/// Requested paths are confined to directory.
 pub struct TftpHandler {
     base_path: PathBuf,
     auth_ip: Option<IpAddr>,
     allow_read: bool,
     allow_write: bool,
+    allowed_files: Option<HashSet<PathBuf>>,
+    overwrite: bool,
 }
 
 impl TftpHandler {
@@ -20,6 +26,8 @@ impl TftpHandler {
             auth_ip: None,
             allow_read: false,
             allow_write: false,
+            allowed_files: None,
+            overwrite: true,
         }
     }
 
@@ -37,6 +45,89 @@ impl TftpHandler {
         self.allow_write = allow;
         self
     }
+
+    /// Serve only allowed files (relative to base path)
+    ///
+    /// Any file is served until first one is allowed.
+    pub fn allow_file(mut self, name: impl AsRef<Path>) -> Self {
+        self.allowed_files
+            .get_or_insert_with(Default::default)
+            .insert(name.as_ref().to_owned());
+        self
+    }
+
+    /// Replace existing files on write (enabled by default)
+    pub fn overwrite(mut self, allow: bool) -> Self {
+        self.overwrite = allow;
+        self
+    }
+
+    fn check_client(&self, client: &SocketAddr) -> Result<(), packet::Error> {
+        match self.auth_ip {
+            Some(ip) if client.ip() != ip => Err(packet::Error::PermissionDenied),
+            _ => Ok(()),
+        }
+    }
+
+    /// Requested path relative to base path
+    fn relative_path(&self, path: &Path) -> Result<PathBuf, packet::Error> {
+        let mut relative = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => relative.push(name),
+                Component::CurDir => (),
+                // absolute paths and parent references lead out of base path
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    return Err(packet::Error::PermissionDenied)
+                }
+            }
+        }
+
+        if relative.as_os_str().is_empty() {
+            return Err(packet::Error::FileNotFound);
+        }
+
+        match &self.allowed_files {
+            Some(files) if !files.contains(&relative) => Err(packet::Error::PermissionDenied),
+            _ => Ok(relative),
+        }
+    }
+
+    /// Real path of requested file which is confined to base path
+    ///
+    /// Symlinks are resolved, so links which lead out of base path are rejected.
+    async fn resolve_path(&self, path: &Path) -> Result<PathBuf, packet::Error> {
+        let relative = self.relative_path(path)?;
+        let base = tokio::fs::canonicalize(&self.base_path)
+            .await
+            .map_err(|_| packet::Error::FileNotFound)?;
+        let path = base.join(relative);
+
+        let real = match tokio::fs::canonicalize(&path).await {
+            Ok(real) => real,
+            // dangling symlink may be followed when file is created
+            Err(_) if tokio::fs::symlink_metadata(&path).await.is_ok() => {
+                return Err(packet::Error::PermissionDenied)
+            }
+            // file which will be created
+            Err(_) => {
+                let (dir, name) = match (path.parent(), path.file_name()) {
+                    (Some(dir), Some(name)) => (dir, name),
+                    _ => return Err(packet::Error::FileNotFound),
+                };
+                tokio::fs::canonicalize(dir)
+                    .await
+                    .map_err(|_| packet::Error::FileNotFound)?
+                    .join(name)
+            }
+        };
+
+        if real.starts_with(&base) {
+            Ok(real)
+        } else {
+            Err(packet::Error::PermissionDenied)
+        }
+    }
 }
 
 #[async_tftp::async_trait]
@@ -51,17 +142,13 @@ impl Handler for TftpHandler {
     ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
         use tokio_util::compat::TokioAsyncReadCompatExt;
 
-        if let Some(ip) = self.auth_ip {
-            if client.ip() != ip {
-                return Err(packet::Error::PermissionDenied);
-            }
-        }
+        self.check_client(client)?;
 
         if !self.allow_read {
             return Err(packet::Error::PermissionDenied);
         }
 
-        let path = self.base_path.join(path);
+        let path = self.resolve_path(path).await?;
 
         match tokio::fs::File::open(path).await {
             Ok(file) => Ok((file.compat(), None)),
@@ -77,21 +164,164 @@ impl Handler for TftpHandler {
     ) -> Result<Self::Writer, packet::Error> {
         use tokio_util::compat::TokioAsyncWriteCompatExt;
 
-        if let Some(ip) = self.auth_ip {
-            if client.ip() != ip {
-                return Err(packet::Error::PermissionDenied);
-            }
-        }
+        self.check_client(client)?;
 
         if !self.allow_write {
             return Err(packet::Error::PermissionDenied);
         }
 
-        let path = self.base_path.join(path);
+        let path = self.resolve_path(path).await?;
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true);
+        if self.overwrite {
+            options.create(true).truncate(true);
+        } else {
+            options.create_new(true);
+        }
 
-        match tokio::fs::File::create(path).await {
+        match options.open(path).await {
             Ok(file) => Ok(file.compat_write()),
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                Err(packet::Error::FileAlreadyExists)
+            }
             Err(_) => Err(packet::Error::FileNotFound),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Served directory with file and secret file outside it
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uboot_tool-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("base/sub")).unwrap();
+        std::fs::write(dir.join("base/sub/image.bin"), b"image").unwrap();
+        std::fs::write(dir.join("secret"), b"secret").unwrap();
+        dir
+    }
+
+    fn client() -> SocketAddr {
+        "127.0.0.1:1234".parse().unwrap()
+    }
+
+    async fn read(handler: &mut TftpHandler, path: &str) -> Result<(), packet::Error> {
+        handler
+            .read_req_open(&client(), Path::new(path))
+            .await
+            .map(|_| ())
+    }
+
+    async fn write(handler: &mut TftpHandler, path: &str) -> Result<(), packet::Error> {
+        handler
+            .write_req_open(&client(), Path::new(path), None)
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn confined_paths() {
+        let dir = temp_dir("confined_paths");
+        let mut handler = TftpHandler::new(dir.join("base"))
+            .allow_read(true)
+            .allow_write(true);
+
+        assert!(read(&mut handler, "sub/image.bin").await.is_ok());
+        assert!(read(&mut handler, "./sub/image.bin").await.is_ok());
+        assert!(read(&mut handler, "missing.bin").await.is_err());
+        for path in [
+            "../secret",
+            "sub/../../secret",
+            "sub/../sub/image.bin",
+            "/etc/passwd",
+        ] {
+            assert!(matches!(
+                read(&mut handler, path).await,
+                Err(packet::Error::PermissionDenied)
+            ));
+            assert!(matches!(
+                write(&mut handler, path).await,
+                Err(packet::Error::PermissionDenied)
+            ));
+        }
+
+        assert_eq!(std::fs::read(dir.join("secret")).unwrap(), b"secret");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlink_escape() {
+        let dir = temp_dir("symlink_escape");
+        std::os::unix::fs::symlink(dir.join("secret"), dir.join("base/link")).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("base/sub/up")).unwrap();
+        std::os::unix::fs::symlink(dir.join("new"), dir.join("base/dangling")).unwrap();
+        std::os::unix::fs::symlink("image.bin", dir.join("base/sub/inner")).unwrap();
+        let mut handler = TftpHandler::new(dir.join("base"))
+            .allow_read(true)
+            .allow_write(true);
+
+        for path in ["link", "sub/up/secret", "dangling"] {
+            assert!(matches!(
+                read(&mut handler, path).await,
+                Err(packet::Error::PermissionDenied)
+            ));
+            assert!(matches!(
+                write(&mut handler, path).await,
+                Err(packet::Error::PermissionDenied)
+            ));
+        }
+        assert!(read(&mut handler, "sub/inner").await.is_ok());
+
+        assert!(!dir.join("new").exists());
+        assert_eq!(std::fs::read(dir.join("secret")).unwrap(), b"secret");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn allowed_files() {
+        let dir = temp_dir("allowed_files");
+        std::fs::write(dir.join("base/other.bin"), b"other").unwrap();
+        let mut handler = TftpHandler::new(dir.join("base"))
+            .allow_read(true)
+            .allow_write(true)
+            .allow_file("sub/image.bin")
+            .allow_file("dump.bin");
+
+        assert!(read(&mut handler, "sub/image.bin").await.is_ok());
+        assert!(matches!(
+            read(&mut handler, "other.bin").await,
+            Err(packet::Error::PermissionDenied)
+        ));
+        assert!(write(&mut handler, "dump.bin").await.is_ok());
+        assert!(matches!(
+            write(&mut handler, "other.bin").await,
+            Err(packet::Error::PermissionDenied)
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn no_overwrite() {
+        let dir = temp_dir("no_overwrite");
+        let mut handler = TftpHandler::new(dir.join("base"))
+            .allow_write(true)
+            .overwrite(false);
+
+        assert!(matches!(
+            write(&mut handler, "sub/image.bin").await,
+            Err(packet::Error::FileAlreadyExists)
+        ));
+        assert!(write(&mut handler, "dump.bin").await.is_ok());
+
+        assert_eq!(
+            std::fs::read(dir.join("base/sub/image.bin")).unwrap(),
+            b"image"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
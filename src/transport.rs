@@ -0,0 +1,86 @@
+use core::{fmt, str::FromStr};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::Error;
+
+/// Bidirectional byte stream to talk with device console
+///
+/// It is implemented for anything which is `AsyncRead + AsyncWrite + Send`,
+/// so serial ports, TCP sockets, ptys and in-memory streams can be used as is.
+pub trait Transport: AsyncRead + AsyncWrite + Send + 'static {}
+
+impl<T> Transport for T where T: AsyncRead + AsyncWrite + Send + 'static {}
+
+/// Console endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Local serial port (`/dev/ttyUSB0`, `COM3`)
+    Serial(String),
+    /// Raw TCP connection (`tcp://host:port`)
+    Tcp(String),
+    /// TCP connection with Telnet negotiation (`telnet://host:port`)
+    Telnet(String),
+    /// Remote serial port with RFC 2217 control (`rfc2217://host:port`)
+    Rfc2217(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        Ok(match src.split_once("://") {
+            Some(("tcp", addr)) => Self::Tcp(addr.into()),
+            Some(("telnet", addr)) => Self::Telnet(addr.into()),
+            Some(("rfc2217", addr)) => Self::Rfc2217(addr.into()),
+            Some((scheme, _)) => return Err(Error::parse("endpoint scheme", scheme)),
+            None => Self::Serial(src.into()),
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serial(name) => f.write_str(name),
+            Self::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Self::Telnet(addr) => write!(f, "telnet://{}", addr),
+            Self::Rfc2217(addr) => write!(f, "rfc2217://{}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn endpoint_serial() {
+        let r: Endpoint = "/dev/ttyUSB0".parse().unwrap();
+        assert_eq!(r, Endpoint::Serial("/dev/ttyUSB0".into()));
+    }
+
+    #[test]
+    fn endpoint_tcp() {
+        let r: Endpoint = "tcp://10.0.0.2:2001".parse().unwrap();
+        assert_eq!(r, Endpoint::Tcp("10.0.0.2:2001".into()));
+        assert_eq!(r.to_string(), "tcp://10.0.0.2:2001");
+    }
+
+    #[test]
+    fn endpoint_telnet() {
+        let r: Endpoint = "telnet://moxa:4001".parse().unwrap();
+        assert_eq!(r, Endpoint::Telnet("moxa:4001".into()));
+    }
+
+    #[test]
+    fn endpoint_rfc2217() {
+        let r: Endpoint = "rfc2217://localhost:7000".parse().unwrap();
+        assert_eq!(r, Endpoint::Rfc2217("localhost:7000".into()));
+        assert_eq!(r.to_string(), "rfc2217://localhost:7000");
+    }
+
+    #[test]
+    fn endpoint_unknown() {
+        assert!("ssh://host".parse::<Endpoint>().is_err());
+    }
+}
@@ -1,6 +1,8 @@
-use crate::{parse_utils::size_u64, Map, Result};
+use crate::{parse_utils::size_u64, Error, Map, Result};
 
 #[derive(Debug, Clone, Default, educe::Educe)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 #[educe(Deref, DerefMut)]
 pub struct Variables {
     #[educe(Deref, DerefMut)]
@@ -8,6 +10,7 @@ pub struct Variables {
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MemRegion {
     pub base: u64,
     pub size: u64,
@@ -17,7 +20,7 @@ impl Variables {
     pub fn get_u32(&self, key: impl AsRef<str>) -> Result<u32> {
         let value = self.get_u64(key)?;
         if value > u32::MAX as u64 {
-            anyhow::bail!("Value out of range u32");
+            return Err(Error::parse("u32 value", value.to_string()));
         }
         Ok(value as u32)
     }
@@ -26,9 +29,8 @@ impl Variables {
         let key = key.as_ref();
         let value = self
             .get(key)
-            .ok_or_else(|| anyhow::anyhow!("Variable '{}' not found", key))?;
-        let (_, value) =
-            size_u64(value).map_err(|err| anyhow::anyhow!("Unable to parse u64 value: {}", err))?;
+            .ok_or_else(|| Error::not_found(format!("Variable '{}'", key)))?;
+        let (_, value) = size_u64(value).map_err(|_| Error::parse("u64 value", value))?;
         Ok(value)
     }
 
@@ -67,8 +69,7 @@ impl Variables {
             )(input)
         }
 
-        let (_, parts) =
-            parse(src.as_ref()).map_err(|err| anyhow::anyhow!("Invalid sequence: {}", err))?;
+        let (_, parts) = parse(src.as_ref()).map_err(|_| Error::parse("mtdparts", src.as_ref()))?;
 
         Ok(parts
             .into_iter()
@@ -117,7 +118,7 @@ impl Variables {
         );
 
         let res: IResult<&str, (&str, &str)> = parse(src.as_ref());
-        let (_, (key, value)) = res.map_err(|err| anyhow::anyhow!("Invalid sequence: {}", err))?;
+        let (_, (key, value)) = res.map_err(|_| Error::parse("variable", src.as_ref()))?;
 
         self.insert(key.into(), value.into());
 
@@ -1,6 +1,7 @@
-use crate::Result;
+use crate::{Error, Result};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VersionInfo {
     pub year: u16,
     pub month: u8,
@@ -48,7 +49,7 @@ impl VersionInfo {
         }
 
         let (_, key) =
-            parse(src.as_ref()).map_err(|err| anyhow::anyhow!("Invalid sequence: {}", err))?;
+            parse(src.as_ref()).map_err(|_| Error::parse("version info", src.as_ref()))?;
         Ok(key)
     }
 }
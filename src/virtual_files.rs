@@ -0,0 +1,495 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_tftp::{packet, server::Handler};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    sync::{mpsc, oneshot, watch},
+};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+use crate::{Error, Map, Result};
+
+type Source = Box<dyn AsyncRead + Send + Unpin>;
+type Sink = Box<dyn AsyncWrite + Send + Unpin>;
+type Reserve = Pin<
+    Box<dyn Future<Output = Result<mpsc::OwnedPermit<Vec<u8>>, mpsc::error::SendError<()>>> + Send>,
+>;
+
+/// Received blocks which are buffered while sink is busy
+const SINK_BLOCKS: usize = 4;
+
+/// Result of finished transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transferred {
+    pub size: u64,
+    pub crc32: u32,
+}
+
+/// Transfer of virtual file
+///
+/// Completion is reported when request is finished: source is read to end
+/// or all expected data is written to sink.
+pub struct Transfer {
+    progress: watch::Receiver<u64>,
+    result: oneshot::Receiver<Result<Transferred>>,
+}
+
+impl Transfer {
+    /// Watch amount of transferred data
+    pub fn progress(&self) -> watch::Receiver<u64> {
+        self.progress.clone()
+    }
+
+    /// Wait until transfer is completed or failed
+    pub async fn finished(self) -> Result<Transferred> {
+        self.result
+            .await
+            .map_err(|_| Error::protocol("Transfer is cancelled"))?
+    }
+}
+
+/// Notifications of transfer
+struct Notify {
+    progress: watch::Sender<u64>,
+    result: oneshot::Sender<Result<Transferred>>,
+}
+
+impl Notify {
+    fn new() -> (Self, Transfer) {
+        let (progress_tx, progress_rx) = watch::channel(0);
+        let (result_tx, result_rx) = oneshot::channel();
+        (
+            Self {
+                progress: progress_tx,
+                result: result_tx,
+            },
+            Transfer {
+                progress: progress_rx,
+                result: result_rx,
+            },
+        )
+    }
+}
+
+enum VirtualFile {
+    Source {
+        source: Source,
+        size: Option<u64>,
+        notify: Notify,
+    },
+    Sink {
+        sink: Sink,
+        size: u64,
+        notify: Notify,
+    },
+}
+
+/// TFTP request handler which serves registered virtual files
+///
+/// Reads are served from [`AsyncRead`] sources and writes are streamed to [`AsyncWrite`] sinks,
+/// so nothing touches filesystem. Each file is transferred once.
+#[derive(Clone, Default)]
+pub struct VirtualFiles {
+    files: Arc<Mutex<Map<String, VirtualFile>>>,
+    auth_ip: Option<IpAddr>,
+}
+
+impl VirtualFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn auth_ip(mut self, ip: IpAddr) -> Self {
+        self.auth_ip = Some(ip);
+        self
+    }
+
+    /// Register file for read requests
+    ///
+    /// Size is reported to client when it is known.
+    /// Transfer is completed when source is read to end and request is finished,
+    /// but lost acknowledge of last block is not detected.
+    pub fn add_source(
+        &self,
+        name: impl Into<String>,
+        source: impl AsyncRead + Send + Unpin + 'static,
+        size: Option<u64>,
+    ) -> Transfer {
+        let (notify, transfer) = Notify::new();
+        self.files.lock().unwrap().insert(
+            name.into(),
+            VirtualFile::Source {
+                source: Box::new(source),
+                size,
+                notify,
+            },
+        );
+        transfer
+    }
+
+    /// Register file for write requests
+    ///
+    /// Expected size is required because aborted request is indistinguishable
+    /// from completed one, so transfer which ends before this size is failed.
+    pub fn add_sink(
+        &self,
+        name: impl Into<String>,
+        sink: impl AsyncWrite + Send + Unpin + 'static,
+        size: u64,
+    ) -> Transfer {
+        let (notify, transfer) = Notify::new();
+        self.files.lock().unwrap().insert(
+            name.into(),
+            VirtualFile::Sink {
+                sink: Box::new(sink),
+                size,
+                notify,
+            },
+        );
+        transfer
+    }
+
+    /// Take registered file of request
+    fn take(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        write: bool,
+    ) -> Result<VirtualFile, packet::Error> {
+        if let Some(ip) = self.auth_ip {
+            if client.ip() != ip {
+                return Err(packet::Error::PermissionDenied);
+            }
+        }
+
+        let name = path.to_str().ok_or(packet::Error::FileNotFound)?;
+        let mut files = self.files.lock().unwrap();
+        match files.get(name) {
+            Some(VirtualFile::Sink { .. }) if write => (),
+            Some(VirtualFile::Source { .. }) if !write => (),
+            Some(_) => return Err(packet::Error::PermissionDenied),
+            None => return Err(packet::Error::FileNotFound),
+        }
+        Ok(files.shift_remove(name).unwrap())
+    }
+}
+
+#[async_tftp::async_trait]
+impl Handler for VirtualFiles {
+    type Reader = Compat<SourceReader>;
+    type Writer = Compat<SinkWriter>;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        match self.take(client, path, false)? {
+            VirtualFile::Source {
+                source,
+                size,
+                notify,
+            } => Ok((
+                SourceReader {
+                    source,
+                    size: 0,
+                    hasher: crc32fast::Hasher::new(),
+                    eof: false,
+                    notify: Some(notify),
+                }
+                .compat(),
+                size,
+            )),
+            VirtualFile::Sink { .. } => Err(packet::Error::PermissionDenied),
+        }
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        match self.take(client, path, true)? {
+            VirtualFile::Sink { sink, size, notify } => {
+                let (data_tx, data_rx) = mpsc::channel(SINK_BLOCKS);
+                tokio::spawn(pump(data_rx, sink, size, notify));
+                Ok(SinkWriter {
+                    data_tx,
+                    reserve: None,
+                }
+                .compat_write())
+            }
+            VirtualFile::Source { .. } => Err(packet::Error::PermissionDenied),
+        }
+    }
+}
+
+/// Reader of source which reports transfer progress
+pub struct SourceReader {
+    source: Source,
+    size: u64,
+    hasher: crc32fast::Hasher,
+    eof: bool,
+    notify: Option<Notify>,
+}
+
+impl AsyncRead for SourceReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        let result = match Pin::new(&mut this.source).poll_read(cx, buf) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        match &result {
+            Ok(()) => {
+                let data = &buf.filled()[filled..];
+                if data.is_empty() && buf.remaining() > 0 {
+                    // completion is reported when request is finished
+                    this.eof = true;
+                } else if let Some(notify) = &this.notify {
+                    this.hasher.update(data);
+                    this.size += data.len() as u64;
+                    notify.progress.send_replace(this.size);
+                }
+            }
+            Err(error) => {
+                if let Some(notify) = this.notify.take() {
+                    let _ =
+                        notify
+                            .result
+                            .send(Err(io::Error::new(error.kind(), error.to_string()).into()));
+                }
+            }
+        }
+
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for SourceReader {
+    fn drop(&mut self) {
+        if let Some(notify) = self.notify.take() {
+            let _ = notify.result.send(if self.eof {
+                Ok(Transferred {
+                    size: self.size,
+                    crc32: self.hasher.clone().finalize(),
+                })
+            } else {
+                Err(Error::protocol(format!(
+                    "Transfer is interrupted after {} bytes",
+                    self.size
+                )))
+            });
+        }
+    }
+}
+
+/// Writer which passes received data to sink
+///
+/// Request is slowed down while sink is busy.
+pub struct SinkWriter {
+    data_tx: mpsc::Sender<Vec<u8>>,
+    reserve: Option<Reserve>,
+}
+
+impl AsyncWrite for SinkWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let reserve = this
+            .reserve
+            .get_or_insert_with(|| Box::pin(this.data_tx.clone().reserve_owned()));
+
+        let permit = match reserve.as_mut().poll(cx) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.reserve = None;
+
+        // sink is failed when data is not accepted
+        Poll::Ready(match permit {
+            Ok(permit) => {
+                permit.send(buf.to_vec());
+                Ok(buf.len())
+            }
+            Err(_) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "Sink is failed")),
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Write received data to sink until request is finished
+async fn pump(mut data_rx: mpsc::Receiver<Vec<u8>>, mut sink: Sink, expected: u64, notify: Notify) {
+    let result = async {
+        let mut size = 0;
+        let mut hasher = crc32fast::Hasher::new();
+
+        while let Some(data) = data_rx.recv().await {
+            sink.write_all(&data).await?;
+            hasher.update(&data);
+            size += data.len() as u64;
+            notify.progress.send_replace(size);
+        }
+        sink.shutdown().await?;
+
+        if size != expected {
+            return Err(Error::protocol(format!(
+                "Transfer is interrupted after {} of {} bytes",
+                size, expected
+            )));
+        }
+        Ok(Transferred {
+            size,
+            crc32: hasher.finalize(),
+        })
+    }
+    .await;
+
+    let _ = notify.result.send(result);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    fn client() -> SocketAddr {
+        "127.0.0.1:1234".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn sink() {
+        let mut files = VirtualFiles::new();
+        let (sink, mut received) = tokio::io::duplex(4096);
+        let transfer = files.add_sink("dump.bin", sink, 1000);
+        let progress = transfer.progress();
+
+        let data: Vec<u8> = (0..1000u32).map(|index| index as u8).collect();
+        let mut writer = files
+            .write_req_open(&client(), Path::new("dump.bin"), None)
+            .await
+            .unwrap();
+        for chunk in data.chunks(512) {
+            writer.write_all(chunk).await.unwrap();
+        }
+        drop(writer);
+
+        assert_eq!(
+            transfer.finished().await.unwrap(),
+            Transferred {
+                size: 1000,
+                crc32: crc32fast::hash(&data)
+            }
+        );
+        assert_eq!(*progress.borrow(), 1000);
+
+        let mut output = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut received, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(output, data);
+
+        // file is transferred once
+        assert!(matches!(
+            files
+                .write_req_open(&client(), Path::new("dump.bin"), None)
+                .await,
+            Err(packet::Error::FileNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn sink_interrupted() {
+        let mut files = VirtualFiles::new();
+        let transfer = files.add_sink("dump.bin", tokio::io::sink(), 1000);
+
+        let mut writer = files
+            .write_req_open(&client(), Path::new("dump.bin"), None)
+            .await
+            .unwrap();
+        writer.write_all(&[0; 512]).await.unwrap();
+        drop(writer);
+
+        assert!(matches!(transfer.finished().await, Err(Error::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn source() {
+        let ip = "127.0.0.1".parse().unwrap();
+        let mut files = VirtualFiles::new().auth_ip(ip);
+        let data = b"uImage".to_vec();
+        let transfer = files.add_source("uImage", std::io::Cursor::new(data.clone()), Some(6));
+        let interrupted = files.add_source("rootfs", std::io::Cursor::new(data.clone()), None);
+
+        assert!(matches!(
+            files
+                .read_req_open(&"10.0.0.1:1234".parse().unwrap(), Path::new("uImage"))
+                .await,
+            Err(packet::Error::PermissionDenied)
+        ));
+        assert!(matches!(
+            files
+                .write_req_open(&client(), Path::new("uImage"), None)
+                .await,
+            Err(packet::Error::PermissionDenied)
+        ));
+
+        let (mut reader, size) = files
+            .read_req_open(&client(), Path::new("rootfs"))
+            .await
+            .unwrap();
+        assert_eq!(size, None);
+        reader.read_exact(&mut [0; 2]).await.unwrap();
+        drop(reader);
+        assert!(matches!(
+            interrupted.finished().await,
+            Err(Error::Protocol(_))
+        ));
+
+        let (mut reader, size) = files
+            .read_req_open(&client(), Path::new("uImage"))
+            .await
+            .unwrap();
+        assert_eq!(size, Some(6));
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, data);
+        assert_eq!(*transfer.progress().borrow(), 6);
+        drop(reader);
+        assert_eq!(
+            transfer.finished().await.unwrap(),
+            Transferred {
+                size: 6,
+                crc32: crc32fast::hash(&data)
+            }
+        );
+    }
+}
@@ -0,0 +1,540 @@
+#![cfg(feature = "simulator")]
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::Duration,
+};
+#[cfg(feature = "tftp")]
+use uboot_tool::TftpOptions;
+use uboot_tool::{AutobootCatch, Error, FlashKind, MemRegion, Simulator, TerminalKey, UBootClient};
+
+fn client(sim: Simulator) -> UBootClient {
+    UBootClient::from_transport(sim.spawn())
+}
+
+#[tokio::test]
+async fn shell_presence_at_prompt() {
+    let mut client = client(Simulator::new());
+    let prompt = client.shell_presence().await.unwrap();
+    assert_eq!(prompt, b"hisilicon # ");
+}
+
+#[tokio::test]
+async fn shell_presence_stop_autoboot() {
+    let sim = Simulator::new()
+        .power_on_delay(Duration::from_millis(200))
+        .autoboot(3, TerminalKey::Any)
+        .tick(Duration::from_millis(200));
+    let mut client = client(sim);
+    let prompt = client.shell_presence().await.unwrap();
+    assert_eq!(prompt, b"hisilicon # ");
+}
+
+#[tokio::test]
+async fn shell_presence_stop_autoboot_by_key() {
+    let sim = Simulator::new()
+        .prompt("=> ")
+        .power_on_delay(Duration::from_millis(200))
+        .autoboot(3, TerminalKey::Key('s'))
+        .tick(Duration::from_millis(200));
+    let mut client = client(sim);
+    let prompt = client.shell_presence().await.unwrap();
+    assert_eq!(prompt, b"=> ");
+}
+
+#[tokio::test]
+async fn shell_presence_stop_keyed_autoboot() {
+    let sim = Simulator::new()
+        .power_on_delay(Duration::from_millis(200))
+        .autoboot(3, "<Esc><Esc>".parse().unwrap())
+        .tick(Duration::from_millis(200));
+    let mut client = client(sim);
+    let prompt = client.shell_presence().await.unwrap();
+    assert_eq!(prompt, b"hisilicon # ");
+}
+
+#[tokio::test]
+async fn shell_presence_stop_autoboot_by_rule() {
+    let sim = Simulator::new()
+        .power_on_delay(Duration::from_millis(200))
+        .autoboot(3, "tpl".parse().unwrap())
+        .autoboot_prompt("Autoboot in {} seconds")
+        .tick(Duration::from_millis(200));
+    let mut client = client(sim);
+    let stops = ["Autoboot in=tpl".parse().unwrap()];
+    let prompt = client.shell_presence_with(&stops).await.unwrap();
+    assert_eq!(prompt, b"hisilicon # ");
+}
+
+#[tokio::test]
+async fn catch_autoboot_without_delay() {
+    let sim = Simulator::new()
+        .power_on_delay(Duration::from_millis(100))
+        .autoboot(0, TerminalKey::Ctrl(b'C'))
+        .tick(Duration::from_millis(50));
+    let mut client = client(sim);
+    let prompt = client
+        .catch_autoboot(&AutobootCatch::default().deadline(Duration::from_secs(5)))
+        .await
+        .unwrap();
+    assert_eq!(prompt, b"hisilicon # ");
+    assert_eq!(client.prompt().unwrap(), b"hisilicon # ");
+
+    let output = client.run("echo ok").await.unwrap();
+    assert_eq!(output.lines, ["ok"]);
+}
+
+#[tokio::test]
+async fn catch_autoboot_by_keys() {
+    let sim = Simulator::new()
+        .prompt("=> ")
+        .power_on_delay(Duration::from_millis(100))
+        .autoboot(2, "<Esc><Esc>".parse().unwrap())
+        .tick(Duration::from_millis(200));
+    let mut client = client(sim);
+    let catch = AutobootCatch::default()
+        .keys("<Esc><Esc>".parse().unwrap())
+        .deadline(Duration::from_secs(5));
+    let prompt = client.catch_autoboot(&catch).await.unwrap();
+    assert_eq!(prompt, b"=> ");
+
+    let output = client.run("echo ok").await.unwrap();
+    assert_eq!(output.lines, ["ok"]);
+}
+
+#[tokio::test]
+async fn catch_autoboot_by_echoed_key() {
+    let sim = Simulator::new()
+        .power_on_delay(Duration::from_millis(100))
+        .autoboot(0, TerminalKey::Key('s'))
+        .tick(Duration::from_millis(50));
+    let mut client = client(sim);
+    let catch = AutobootCatch::default()
+        .keys(TerminalKey::Key('s'))
+        .deadline(Duration::from_secs(5));
+    let prompt = client.catch_autoboot(&catch).await.unwrap();
+    assert_eq!(prompt, b"hisilicon # ");
+
+    let output = client.run("echo ok").await.unwrap();
+    assert_eq!(output.lines, ["ok"]);
+}
+
+#[tokio::test]
+async fn catch_autoboot_deadline() {
+    let sim = Simulator::new()
+        .power_on_delay(Duration::from_millis(100))
+        .autoboot(0, TerminalKey::Key('s'))
+        .tick(Duration::from_millis(50));
+    let mut client = client(sim);
+    let result = client
+        .catch_autoboot(&AutobootCatch::default().deadline(Duration::from_millis(500)))
+        .await;
+    assert!(matches!(result, Err(Error::Timeout { .. })));
+}
+
+/// Device which answers at 115200 and 921600 only
+fn reopened(rate: u32) -> tokio::io::DuplexStream {
+    let (host, mut device) = tokio::io::duplex(1024);
+    tokio::spawn(async move {
+        if rate == 115200 || rate == 921600 {
+            let _ = Simulator::new().run(device).await;
+            return;
+        }
+        let mut buf = [0u8; 64];
+        while let Ok(len) = device.read(&mut buf).await {
+            if len == 0 {
+                break;
+            }
+            // garbled response
+            let _ = device.write_all(b"\x80\xfe\x00\xf8\x1f\xe0\x8c").await;
+        }
+    });
+    host
+}
+
+#[tokio::test]
+async fn switch_baud() {
+    let mut client = client(Simulator::new());
+    client.set_baud_opener(115200, |rate| async move { Ok(reopened(rate)) });
+    client.shell_presence().await.unwrap();
+
+    client.switch_baud(921600).await.unwrap();
+    assert_eq!(client.baud_rate(), Some(921600));
+    let output = client.run("echo fast").await.unwrap();
+    assert_eq!(output.lines, ["fast"]);
+}
+
+#[tokio::test]
+async fn switch_baud_fallback() {
+    let mut client = client(Simulator::new());
+    client.set_baud_opener(115200, |rate| async move { Ok(reopened(rate)) });
+    client.shell_presence().await.unwrap();
+
+    let result = client.switch_baud(230400).await;
+    assert!(matches!(result, Err(Error::Protocol(_))));
+    assert_eq!(client.baud_rate(), Some(115200));
+    let output = client.run("echo slow").await.unwrap();
+    assert_eq!(output.lines, ["slow"]);
+}
+
+#[tokio::test]
+async fn switch_baud_restore() {
+    let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut client = client(Simulator::new());
+    let log = received.clone();
+    client.set_baud_opener(115200, move |rate| {
+        let log = log.clone();
+        async move {
+            if rate == 115200 {
+                return Ok(reopened(rate));
+            }
+            // device which switched but talks garbage
+            let (host, mut device) = tokio::io::duplex(1024);
+            tokio::spawn(async move {
+                let mut buf = [0u8; 64];
+                while let Ok(len) = device.read(&mut buf).await {
+                    if len == 0 {
+                        break;
+                    }
+                    log.lock().unwrap().extend(&buf[..len]);
+                    let _ = device.write_all(b"\x80\xfe\x00\xf8").await;
+                }
+            });
+            Ok(host)
+        }
+    });
+    client.shell_presence().await.unwrap();
+
+    let result = client.switch_baud(230400).await;
+    assert!(matches!(result, Err(Error::Protocol(_))));
+    let received = String::from_utf8_lossy(&received.lock().unwrap()).into_owned();
+    assert!(received.contains("setenv baudrate 115200"));
+    assert_eq!(client.baud_rate(), Some(115200));
+}
+
+#[tokio::test]
+async fn switch_baud_unsupported() {
+    let mut client = client(Simulator::new());
+    client.set_baud_opener(115200, |rate| async move { Ok(reopened(rate)) });
+    client.shell_presence().await.unwrap();
+
+    let result = client.switch_baud(12345).await;
+    assert!(matches!(result, Err(Error::Protocol(_))));
+    assert_eq!(client.baud_rate(), Some(115200));
+    let output = client.run("echo same").await.unwrap();
+    assert_eq!(output.lines, ["same"]);
+}
+
+#[tokio::test]
+async fn run_command() {
+    let client = client(Simulator::new());
+    let output = client.run("echo hello; echo world").await.unwrap();
+    assert_eq!(output.command, "echo hello; echo world");
+    assert_eq!(output.lines, ["hello", "world"]);
+    assert_eq!(output.code, Some(0));
+    assert!(output.success());
+}
+
+#[tokio::test]
+async fn run_command_with_known_prompt() {
+    let mut client = client(Simulator::new());
+    client.shell_presence().await.unwrap();
+    assert_eq!(client.prompt().unwrap(), b"hisilicon # ");
+
+    let mut exec = client.execute("version").await.unwrap();
+    assert_eq!(
+        exec.next_line().await.unwrap().unwrap(),
+        "U-Boot 2016.11-g2fc5f58-dirty"
+    );
+    assert_eq!(exec.next_line().await.unwrap(), None);
+    assert!(exec.is_finished());
+    assert_eq!(exec.code(), Some(0));
+}
+
+#[tokio::test]
+async fn run_command_failure() {
+    let client = client(Simulator::new());
+    let output = client.run("reset_all").await.unwrap();
+    assert_eq!(output.lines, ["Unknown command 'reset_all' - try 'help'"]);
+    assert_eq!(output.code, Some(1));
+    assert!(matches!(
+        output.check(),
+        Err(Error::CommandFailed { code: 1, .. })
+    ));
+}
+
+#[tokio::test]
+async fn concurrent_commands() {
+    let client = client(Simulator::new());
+    let tasks: Vec<_> = (0..4)
+        .map(|n| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                for _ in 0..5 {
+                    let output = client.run(format!("echo task{}", n)).await.unwrap();
+                    assert_eq!(output.lines, [format!("task{}", n)]);
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn session_blocks_other_handles() {
+    let client = client(Simulator::new());
+    let session = client.session().await;
+
+    let other = client.clone();
+    let pending = tokio::spawn(async move { other.run("echo other").await.unwrap() });
+
+    // commands of session itself does not wait
+    let output = session.run("echo first").await.unwrap();
+    assert_eq!(output.lines, ["first"]);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!pending.is_finished());
+
+    drop(session);
+    assert_eq!(pending.await.unwrap().lines, ["other"]);
+}
+
+#[tokio::test]
+async fn get_version() {
+    let mut client = client(Simulator::new());
+    let version = client.get_version().await.unwrap();
+    assert_eq!(version.year, 2016);
+    assert_eq!(version.month, 11);
+    assert_eq!(version.revision, "g2fc5f58");
+    assert_eq!(version.suffix, "dirty");
+}
+
+#[tokio::test]
+async fn get_flash_info() {
+    let mut client = client(Simulator::new());
+    let info = client.get_flash_info().await.unwrap();
+    assert_eq!(info.kind, FlashKind::Spi);
+    assert_eq!(info.block, 64 << 10);
+    assert_eq!(info.size, 256 << 10);
+    assert_eq!(info.count, 1);
+    assert_eq!(info.id, [0xa1, 0x40, 0x17]);
+    assert_eq!(info.name, "XM_FM25Q64");
+}
+
+#[tokio::test]
+async fn get_flash_info_nand() {
+    let mut client = client(Simulator::new().nand(true));
+    let info = client.get_flash_info().await.unwrap();
+    assert_eq!(info.kind, FlashKind::Nand);
+    assert_eq!(info.size, 256 << 10);
+}
+
+#[tokio::test]
+async fn get_environ() {
+    let mut client = client(Simulator::new().env("foo", "bar baz"));
+    let environ = client.get_environ().await.unwrap();
+    assert_eq!(environ.get("bootdelay").unwrap(), "1");
+    assert_eq!(environ.get("foo").unwrap(), "bar baz");
+    assert!(environ.get("bootargs").unwrap().contains("mtdparts="));
+}
+
+#[tokio::test]
+async fn get_bdinfo() {
+    let mut client = client(Simulator::new());
+    let bdinfo = client.get_bdinfo().await.unwrap();
+    assert_eq!(bdinfo.get("arch_number").unwrap(), "0x00001F40");
+    assert_eq!(bdinfo.get("ip_addr").unwrap(), "192.168.1.10");
+}
+
+#[tokio::test]
+async fn get_ram_info() {
+    let mut client = client(Simulator::new().ram(0x8000_0000, 0x0200_0000));
+    let ram = client.get_ram_info().await.unwrap();
+    assert_eq!(ram.base, 0x8000_0000);
+    assert_eq!(ram.size, 0x0200_0000);
+}
+
+#[tokio::test]
+async fn get_mtd_parts() {
+    let mut client = client(Simulator::new());
+    let parts = client.get_mtd_parts().await.unwrap();
+    let parts: Vec<_> = parts
+        .iter()
+        .map(|(name, region)| (name.as_str(), region.base, region.size))
+        .collect();
+    assert_eq!(
+        parts,
+        [
+            ("boot", 0x0, 0x10000),
+            ("kernel", 0x10000, 0x20000),
+            ("rootfs", 0x30000, 0x10000)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn get_device_info() {
+    let mut client = client(Simulator::new());
+    let info = client.get_device_info().await.unwrap();
+    assert_eq!(info.version.year, 2016);
+    assert_eq!(info.flash.kind, FlashKind::Spi);
+    assert_eq!(info.ram.base, 0x4000_0000);
+    assert_eq!(info.mtd_parts.len(), 3);
+}
+
+#[tokio::test]
+async fn spi_flash_cmd() {
+    let mut client = client(Simulator::new());
+    client.spi_flash_cmd("probe 0").await.unwrap();
+}
+
+#[tokio::test]
+async fn read_mtd_part_and_crc32() {
+    let sim = Simulator::new();
+    let expected = crc32fast::hash(&sim.flash()[0x10000..0x30000]);
+    let mut client = client(sim);
+    let region = MemRegion {
+        base: 0x10000,
+        size: 0x20000,
+    };
+    client.read_mtd_part(&region, 0x4200_0000).await.unwrap();
+    let crc = client.calc_crc32(0x4200_0000, region.size).await.unwrap();
+    assert_eq!(crc, expected);
+}
+
+#[tokio::test]
+async fn dump_mtd_part() {
+    let sim = Simulator::new();
+    let expected = sim.flash()[0x30000..0x40000].to_vec();
+    let mut client = client(sim);
+    let region = MemRegion {
+        base: 0x30000,
+        size: 0x10000,
+    };
+
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(16);
+    let progress = tokio::spawn(async move {
+        let mut last = 0;
+        while let Some(off) = progress_rx.recv().await {
+            last = off;
+        }
+        last
+    });
+
+    let mut data = Vec::new();
+    client
+        .dump_mtd_part(&mut data, &region, 0x4200_0000, progress_tx)
+        .await
+        .unwrap();
+    assert_eq!(data, expected);
+    assert_eq!(progress.await.unwrap(), region.size);
+}
+
+/// Empty directory for received files
+#[cfg(feature = "tftp")]
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("uboot_tool-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Local server on free port, so root is not required
+#[cfg(feature = "tftp")]
+fn tftp_options() -> TftpOptions {
+    let ip: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+    TftpOptions::new(ip).server_ip(ip).port(0)
+}
+
+#[cfg(feature = "tftp")]
+#[tokio::test]
+async fn dump_mtd_part_tftp() {
+    let sim = Simulator::new();
+    let expected = sim.flash()[0x10000..0x30000].to_vec();
+    let mut client = client(sim);
+    let region = MemRegion {
+        base: 0x10000,
+        size: 0x20000,
+    };
+
+    let tftp = tftp_options();
+    let (sink, mut data) = tokio::io::duplex(region.size as usize);
+
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(16);
+    let progress = tokio::spawn(async move {
+        let mut last = 0;
+        while let Some(off) = progress_rx.recv().await {
+            last = off;
+        }
+        last
+    });
+
+    client
+        .dump_mtd_part_tftp(&tftp, "kernel", sink, &region, 0x4200_0000, progress_tx)
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    data.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, expected);
+    assert_eq!(progress.await.unwrap(), region.size);
+    assert!(!client.get_environ().await.unwrap().contains_key("tftpdstp"));
+}
+
+#[cfg(feature = "tftp")]
+#[tokio::test]
+async fn tftp_send() {
+    let sim = Simulator::new();
+    let expected = sim.flash()[..0x1200].to_vec();
+    let mut client = client(sim);
+
+    let tftp = tftp_options();
+    let file = temp_dir("tftp_send").join("boot.bin");
+
+    let (progress_tx, _progress_rx) = tokio::sync::mpsc::channel(16);
+    client.spi_flash_cmd("probe 0").await.unwrap();
+    client
+        .run("sf read 0x42000000 0 0x1200")
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+    client
+        .tftp_send(&tftp, &file, 0x4200_0000, 0x1200, progress_tx)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&file).unwrap(), expected);
+    std::fs::remove_dir_all(file.parent().unwrap()).unwrap();
+}
+
+#[cfg(feature = "tftp")]
+#[tokio::test]
+async fn tftp_load() {
+    let mut client = client(Simulator::new());
+    let image: Vec<u8> = (0..0x5432u32).map(|index| (index * 7 / 8) as u8).collect();
+
+    let tftp = tftp_options();
+    let file = temp_dir("tftp_load").join("uImage");
+    std::fs::write(&file, &image).unwrap();
+    client.run("setenv tftpdstp 6969").await.unwrap();
+
+    let (progress_tx, _progress_rx) = tokio::sync::mpsc::channel(16);
+    let size = client
+        .tftp_load(&tftp, &file, 0x4200_0000, progress_tx)
+        .await
+        .unwrap();
+    assert_eq!(size, image.len() as u64);
+    assert_eq!(
+        client.calc_crc32(0x4200_0000, size).await.unwrap(),
+        crc32fast::hash(&image)
+    );
+    assert_eq!(
+        client.run("printenv filesize").await.unwrap().lines,
+        ["filesize=5432"]
+    );
+    assert_eq!(
+        client.run("printenv tftpdstp").await.unwrap().lines,
+        ["tftpdstp=6969"]
+    );
+    std::fs::remove_dir_all(file.parent().unwrap()).unwrap();
+}